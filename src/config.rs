@@ -0,0 +1,306 @@
+//! Layered configuration
+//!
+//! Every option has a snake_case key (e.g. `git_protected`) and is resolved from, in increasing precedence:
+//! built-in defaults, the config file (`git_protected = "main"`), the environment (`OMNIPROMPT_GIT_PROTECTED=main`)
+//! and command-line flags (`--git-protected=main`).
+//!
+//! The config file can also hold profiles, which override its options in matching directories:
+//!
+//! ```toml
+//! profiles = "work, home"
+//!
+//! [profile.work]
+//! path = "~/work/.*"
+//! git_count = 1
+//! ```
+//!
+//! The first profile listed in `profiles` whose `path` regex matches the current directory applies, falling back to
+//! the `default` profile if there is one.
+use anyhow::{Context, Result, anyhow};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+pub type Layer = HashMap<String, String>;
+
+/// Built-in defaults, the lowest precedence layer
+const DEFAULTS: &[(&str, &str)] = &[
+    ("banner_color", "1"),
+    ("git_detached_warning", "1"),
+    ("git_owner_path", "README.md"),
+    ("net_down_glyph", "↓"),
+    ("net_up_glyph", "↑"),
+    ("row_band0", "236"),
+    ("row_bandn", "238"),
+    ("sep_row0", " - "),
+    ("sep_rown", "-"),
+    ("single_line_separator", r"\n"),
+];
+
+#[derive(Clone)]
+pub struct Config {
+    defaults: Layer,
+    file: Layer,
+    /// The options of the selected profile, keyed without their `profile.<name>.` prefix
+    profile: Layer,
+    /// The process environment, keyed by variable name
+    env: HashMap<String, String>,
+    flags: Layer,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new(Layer::new(), HashMap::new(), Layer::new())
+    }
+}
+
+impl Config {
+    pub fn new(file: Layer, env: HashMap<String, String>, flags: Layer) -> Self {
+        Self::with_defaults(DEFAULTS.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(), file, env, flags)
+    }
+
+    pub fn with_defaults(defaults: Layer, file: Layer, env: HashMap<String, String>, flags: Layer) -> Self {
+        Self {
+            defaults,
+            file,
+            profile: Layer::new(),
+            env,
+            flags,
+        }
+    }
+
+    /// Resolve the configuration of this process from the config file, the environment and `flags`
+    pub fn load(flags: Layer) -> Self {
+        let file = match path().map(|p| (std::fs::read_to_string(&p), p)) {
+            Some((Ok(contents), p)) => parse_file(&contents).unwrap_or_else(|e| {
+                eprintln!("omniprompt: ignoring {}: {:#}", p.display(), e);
+                Layer::new()
+            }),
+            _ => Layer::new(),
+        };
+        let mut config = Self::new(file, std::env::vars_os().filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?))).collect(), flags);
+        if let Ok(cwd) = std::env::current_dir() {
+            if let Err(e) = config.select_profile(&cwd, dirs::home_dir().as_deref()) {
+                eprintln!("omniprompt: ignoring profiles: {:#}", e);
+            }
+        }
+        config
+    }
+
+    /// Apply the first profile listed in `profiles` whose `path` matches `cwd`, or else the `default` profile,
+    /// returning the name of the one applied
+    pub fn select_profile(&mut self, cwd: &Path, home_dir: Option<&Path>) -> Result<Option<String>> {
+        let cwd = cwd.to_string_lossy();
+        let mut selected = None;
+        for name in self.get("profiles").unwrap_or_default().split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let key = format!("profile.{}.path", name);
+            let pattern = match self.file.get(&key) {
+                Some(pattern) => pattern,
+                None => continue,
+            };
+            let pattern = match (pattern.strip_prefix('~'), home_dir) {
+                (Some(rest), Some(home_dir)) => format!("{}{}", regex::escape(&home_dir.to_string_lossy()), rest),
+                _ => pattern.clone(),
+            };
+            let re = regex::Regex::new(&format!("^(?:{})$", pattern)).with_context(|| format!("parsing {}", key))?;
+            if re.is_match(&cwd) {
+                selected = Some(name.to_string());
+                break;
+            }
+        }
+        if selected.is_none() && self.file.keys().any(|k| k.starts_with("profile.default.")) {
+            selected = Some("default".to_string());
+        }
+        self.profile = match &selected {
+            Some(name) => {
+                let prefix = format!("profile.{}.", name);
+                self.file.iter().filter_map(|(k, v)| Some((k.strip_prefix(&prefix)?.to_string(), v.clone()))).collect()
+            }
+            None => Layer::new(),
+        };
+        Ok(selected)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.flags.get(key)
+            .or_else(|| self.env.get(&env_name(key)))
+            .or_else(|| self.profile.get(key))
+            .or_else(|| self.file.get(key))
+            .or_else(|| self.defaults.get(key))
+            .map(String::as_str)
+    }
+
+    /// Every option set in any layer with its resolved value, sorted by key. Of the environment, only `OMNIPROMPT_*`
+    /// variables count.
+    pub fn options(&self) -> Vec<(String, String)> {
+        let env = self.env.keys().filter_map(|name| Some(name.strip_prefix("OMNIPROMPT_")?.to_ascii_lowercase()));
+        let keys: std::collections::BTreeSet<String> = self.defaults.keys().chain(self.file.keys()).chain(self.profile.keys()).chain(self.flags.keys()).cloned().chain(env).collect();
+        keys.into_iter().filter_map(|key| {
+            let value = self.get(&key)?.to_string();
+            Some((key, value))
+        }).collect()
+    }
+
+    /// The environment the prompt was rendered in, sorted by name, leaving out the `OMNIPROMPT_*` variables that
+    /// `options` covers
+    pub fn environment(&self) -> Vec<(&str, &str)> {
+        let mut env: Vec<(&str, &str)> = self.env.iter()
+            .filter(|(name, _)| !name.starts_with("OMNIPROMPT_"))
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        env.sort_unstable();
+        env
+    }
+
+    /// A variable of the environment the prompt was rendered in, e.g. `OLDPWD`
+    pub fn env(&self, name: &str) -> Option<&str> {
+        self.env.get(name).map(String::as_str)
+    }
+
+    /// Parse an option into `T`, reporting which option was malformed
+    pub fn parse<T: std::str::FromStr>(&self, key: &str) -> Result<Option<T>> where T::Err: std::error::Error + Send + Sync + 'static {
+        self.get(key).map(|v| v.parse::<T>().with_context(|| format!("parsing {} {:?}", key, v))).transpose()
+    }
+
+    /// Whether a boolean option is set to a truthy value (`1`, `true`, `yes`, `on`)
+    pub fn is_enabled(&self, key: &str) -> bool {
+        matches!(self.get(key).map(str::to_ascii_lowercase).as_deref(), Some("1" | "true" | "yes" | "on"))
+    }
+}
+
+fn env_name(key: &str) -> String {
+    format!("OMNIPROMPT_{}", key.to_ascii_uppercase())
+}
+
+/// `$XDG_CONFIG_HOME/omniprompt/config.toml`
+pub fn path() -> Option<PathBuf> {
+    dirs::config_dir().map(|d| d.join("omniprompt").join("config.toml"))
+}
+
+/// Parse the flat subset of TOML used by the config file: `key = value` pairs, `#` comments and `[section]` headers,
+/// which prefix the keys that follow them (`[a]` then `b = 1` is `a.b`)
+pub fn parse_file(contents: &str) -> Result<Layer> {
+    let mut layer = Layer::new();
+    let mut section = String::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = format!("{}.", name.trim());
+            continue;
+        }
+        let (key, value) = line.split_once('=').ok_or_else(|| anyhow!("line {}: expected `key = value`", i + 1))?;
+        let value = value.trim();
+        let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"'))
+            .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+            .unwrap_or(value);
+        layer.insert(format!("{}{}", section, key.trim()), value.to_string());
+    }
+    Ok(layer)
+}
+
+/// Split the command line into positional arguments and `--some-option[=value]` flags, keyed as `some_option`
+pub fn parse_args(args: impl IntoIterator<Item = OsString>) -> Result<(Vec<String>, Layer)> {
+    let mut positional = Vec::new();
+    let mut flags = Layer::new();
+    for arg in args {
+        let arg = arg.into_string().map_err(|a| anyhow!("Invalid UTF-8 in argument {:?}", a))?;
+        match arg.strip_prefix("--") {
+            Some(flag) => {
+                let (name, value) = flag.split_once('=').unwrap_or((flag, "1"));
+                if name.is_empty() {
+                    return Err(anyhow!("Invalid flag {:?}", arg));
+                }
+                flags.insert(name.replace('-', "_"), value.to_string());
+            }
+            None => positional.push(arg),
+        }
+    }
+    Ok((positional, flags))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn layer(pairs: &[(&str, &str)]) -> Layer {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn precedence() {
+        let defaults = layer(&[("a", "default"), ("b", "default"), ("c", "default"), ("d", "default")]);
+        let file = layer(&[("a", "file"), ("b", "file"), ("c", "file")]);
+        let env = layer(&[("OMNIPROMPT_A", "env"), ("OMNIPROMPT_B", "env")]);
+        let flags = layer(&[("a", "flag")]);
+        let config = Config::with_defaults(defaults, file, env, flags);
+        assert_eq!(config.get("a"), Some("flag"));
+        assert_eq!(config.get("b"), Some("env"));
+        assert_eq!(config.get("c"), Some("file"));
+        assert_eq!(config.get("d"), Some("default"));
+        assert_eq!(config.get("e"), None);
+    }
+
+    #[test]
+    fn options() {
+        let config = Config::with_defaults(layer(&[("a", "default")]), layer(&[("b", "file")]), layer(&[("OMNIPROMPT_A", "env"), ("PATH", "/bin")]), layer(&[("c", "flag")]));
+        assert_eq!(config.options(), vec![("a".to_string(), "env".to_string()), ("b".to_string(), "file".to_string()), ("c".to_string(), "flag".to_string())]);
+    }
+
+    #[test]
+    fn environment() {
+        let config = Config::new(Layer::new(), layer(&[("PATH", "/bin"), ("OMNIPROMPT_A", "env"), ("OLDPWD", "/tmp")]), Layer::new());
+        assert_eq!(config.environment(), vec![("OLDPWD", "/tmp"), ("PATH", "/bin")]);
+    }
+
+    #[test]
+    fn enabled() {
+        let config = Config::new(Layer::new(), layer(&[("OMNIPROMPT_A", "1"), ("OMNIPROMPT_B", "0"), ("OMNIPROMPT_C", "True")]), Layer::new());
+        assert!(config.is_enabled("a"));
+        assert!(!config.is_enabled("b"));
+        assert!(config.is_enabled("c"));
+        assert!(!config.is_enabled("d"));
+    }
+
+    #[test]
+    fn file() {
+        let parsed = parse_file("# comment\ngit_protected = \"main|release/.*\"\n\n[section]\nkey = 'value'\nnumber = 3\n").unwrap();
+        assert_eq!(parsed, layer(&[("git_protected", "main|release/.*"), ("section.key", "value"), ("section.number", "3")]));
+        assert!(parse_file("no equals sign").is_err());
+    }
+
+    #[test]
+    fn profiles() {
+        let file = parse_file(concat!(
+            "profiles = \"work, oss\"\ngit_count = 0\nframe = square\n",
+            "[profile.work]\npath = \"~/work(/.*)?\"\ngit_count = 1\n",
+            "[profile.oss]\npath = \"/src/.*\"\ngit_count = 2\n",
+            "[profile.default]\nframe = rounded\n",
+        )).unwrap();
+        let home = Path::new("/home/user");
+        let mut config = Config::new(file, HashMap::new(), Layer::new());
+        assert_eq!(config.select_profile(Path::new("/home/user/work/repo"), Some(home)).unwrap().as_deref(), Some("work"));
+        assert_eq!(config.get("git_count"), Some("1"));
+        assert_eq!(config.get("frame"), Some("square"));
+        assert_eq!(config.select_profile(Path::new("/src/omniprompt"), Some(home)).unwrap().as_deref(), Some("oss"));
+        assert_eq!(config.get("git_count"), Some("2"));
+        assert_eq!(config.select_profile(Path::new("/home/user/play"), Some(home)).unwrap().as_deref(), Some("default"));
+        assert_eq!(config.get("git_count"), Some("0"));
+        assert_eq!(config.get("frame"), Some("rounded"));
+
+        let mut config = Config::new(parse_file("profiles = work\n[profile.work]\npath = /work\n").unwrap(), HashMap::new(), Layer::new());
+        assert_eq!(config.select_profile(Path::new("/home"), None).unwrap(), None);
+        let mut config = Config::new(parse_file("profiles = work\n[profile.work]\npath = (\n").unwrap(), HashMap::new(), Layer::new());
+        assert!(config.select_profile(Path::new("/home"), None).is_err());
+    }
+
+    #[test]
+    fn args() {
+        let (positional, flags) = parse_args(["1", "--git-protected=main", "--enabled"].iter().map(OsString::from)).unwrap();
+        assert_eq!(positional, vec!["1".to_string()]);
+        assert_eq!(flags, layer(&[("git_protected", "main"), ("enabled", "1")]));
+        assert!(parse_args(["--=x"].iter().map(OsString::from)).is_err());
+    }
+}
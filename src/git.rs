@@ -0,0 +1,1074 @@
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::colors::Colorizer;
+use crate::config::Config;
+
+/// The `Field::Git` contents for the repository containing `dir`, or nothing outside of a repository
+pub fn print<T: Write>(stream: &mut T, dir: &Path, config: &Config) -> Result<()> {
+    let repo = match gix::discover(dir) {
+        Ok(repo) => repo,
+        Err(_) => return Ok(()),
+    };
+    let head = repo.head().context("trying to get HEAD")?;
+    // Glyphs for what HEAD is on, in place of the detached warning
+    let ref_type = config.is_enabled("git_ref_type");
+    let glyph = |glyph: &'static str, word: &'static str| if crate::colors::is_plain() { format!("{} ", word) } else { glyph.to_string() };
+    match head.referent_name() {
+        Some(referent) => {
+            if ref_type {
+                write!(stream, "{}", glyph("⎇", "branch").dim())?;
+            }
+            let name = referent.file_name();
+            let protected = match config.get("git_protected") {
+                Some(pattern) => is_protected_branch(&referent.shorten().to_string(), pattern)?,
+                None => false,
+            };
+            if protected {
+                write!(stream, "{}", paint(config, "git_color_protected", "red", name).bold())?;
+            } else {
+                write!(stream, "{}", paint(config, "git_color_branch", "yellow", name))?;
+            }
+        }
+        None => {
+            let described = match head.id() {
+                Some(id) => describe(&repo, id.detach())?,
+                None => None,
+            };
+            match (ref_type, &described) {
+                (true, Some((_, 0))) => write!(stream, "{}", glyph("🏷", "tag").dim())?,
+                (true, _) => write!(stream, "{}", glyph("⚠", "detached").red().bold())?,
+                (false, _) if config.is_enabled("git_detached_warning") => write!(stream, "{} ", "⚠DETACHED".red().bold())?,
+                (false, _) => {}
+            }
+            // Like `git describe --tags`: the nearest tag, then how many commits past it and the abbreviated id
+            match (described, head.id()) {
+                (Some((tag, 0)), _) => write!(stream, "{}", paint(config, "git_color_detached", "yellow", tag))?,
+                (Some((tag, distance)), Some(id)) => {
+                    let described = format!("{}-{}-g{}", tag, distance, id.shorten_or_id());
+                    write!(stream, "{}", paint(config, "git_color_detached", "yellow", described))?;
+                }
+                (_, Some(id)) => write!(stream, "{}", paint(config, "git_color_detached", "yellow", id.shorten_or_id()))?,
+                (_, None) => write!(stream, "{}", "<UNKNOWN>".yellow())?,
+            }
+        }
+    }
+    // An operation left half-done, which changes what commands like `git commit` do
+    if let Some(state) = repo.state() {
+        use gix::state::InProgress;
+        let state = match state {
+            InProgress::ApplyMailbox | InProgress::ApplyMailboxRebase => "AM",
+            InProgress::Bisect => "BISECT",
+            InProgress::CherryPick | InProgress::CherryPickSequence => "CHERRY-PICK",
+            InProgress::Merge => "MERGE",
+            InProgress::Rebase | InProgress::RebaseInteractive => "REBASE",
+            InProgress::Revert | InProgress::RevertSequence => "REVERT",
+        };
+        match (state, bisect_steps(&repo)?) {
+            ("BISECT", Some(steps)) => write!(stream, "{}", paint(config, "git_color_operation", "red", format_args!("|BISECT ~{} left", steps)).bold())?,
+            _ => write!(stream, "{}", paint(config, "git_color_operation", "red", format_args!("|{}", state)).bold())?,
+        }
+    }
+    // Scanning the working tree can be slow in large repositories, so it's opt-in
+    if config.is_enabled("git_dirty") && repo.work_dir().is_some() {
+        let (changed, untracked) = worktree_state(&repo)?;
+        if changed {
+            write!(stream, "{}", paint(config, "git_color_dirty", "yellow", "*"))?;
+        }
+        if untracked {
+            write!(stream, "{}", paint(config, "git_color_dirty", "yellow", "+"))?;
+        }
+    }
+    // Walking history is opt-in for the same reason
+    if config.is_enabled("git_upstream") {
+        if let Some((ahead, behind)) = upstream_divergence(&repo)? {
+            let mut counts = String::new();
+            if ahead > 0 {
+                counts.push_str(&format!("↑{}", ahead));
+            }
+            if behind > 0 {
+                counts.push_str(&format!("↓{}", behind));
+            }
+            if !counts.is_empty() {
+                write!(stream, " {}", paint(config, "git_color_diverged", "cyan", counts))?;
+            }
+        }
+    }
+    // Only part of the tree is checked out
+    let sparse = repo.config_snapshot().boolean("core.sparseCheckout")
+        .unwrap_or_else(|| repo.path().join("info").join("sparse-checkout").exists());
+    if sparse {
+        write!(stream, " {}", "sparse".dim())?;
+    }
+    // Usually a misconfigured identity, or someone else's commit on top
+    if config.is_enabled("git_author_check") {
+        if let (Some(email), Ok(commit)) = (repo.config_snapshot().string("user.email"), repo.head_commit()) {
+            if commit.author()?.email != email.as_ref() {
+                write!(stream, " {}", "≠author".yellow().dim())?;
+            }
+        }
+    }
+    if config.is_enabled("git_notes") && has_notes(&repo)? {
+        write!(stream, " {}", (if crate::colors::is_plain() { "notes" } else { "📝" }).dim())?;
+    }
+    // Stashes made on other branches are easily forgotten after switching away
+    if config.is_enabled("git_stash_other") {
+        let other = foreign_stashes(&repo)?;
+        if other > 0 {
+            write!(stream, " {}", format_args!("⚑(other:{})", other).yellow())?;
+        }
+    }
+    // Another git process holds the index, so commands touching it will fail
+    if config.is_enabled("git_lock") && repo.path().join("index.lock").exists() {
+        write!(stream, " {}", (if crate::colors::is_plain() { "locked" } else { "🔒locked" }).red())?;
+    }
+    // `git_hooks = framework` also requires the hook to have been installed by the pre-commit framework
+    if let Some(mode) = config.get("git_hooks").filter(|mode| *mode == "framework" || config.is_enabled("git_hooks")) {
+        if has_pre_commit_hook(repo.common_dir(), mode == "framework") {
+            write!(stream, " {}", "hooks✓".dim())?;
+        } else {
+            write!(stream, " {}", "hooks✗".red())?;
+        }
+    }
+    Ok(())
+}
+
+/// First-parent commits searched for the last change to `git_owner_path` before giving up
+const OWNER_DEPTH: usize = 1000;
+
+/// The `Field::GitOwner` contents: the author of the last commit touching `git_owner_path` (the README by default)
+/// relative to `dir`, or nothing when the file isn't tracked or wasn't changed within the last `OWNER_DEPTH` commits
+pub fn print_owner<T: Write>(stream: &mut T, dir: &Path, config: &Config) -> Result<()> {
+    let repo = match gix::discover(dir) {
+        Ok(repo) => repo,
+        Err(_) => return Ok(()),
+    };
+    let (workdir, file) = match (repo.work_dir().map(Path::canonicalize), config.get("git_owner_path").map(|p| dir.join(p).canonicalize())) {
+        (Some(Ok(workdir)), Some(Ok(file))) => (workdir, file),
+        _ => return Ok(()),
+    };
+    let relpath = match file.strip_prefix(&workdir) {
+        Ok(relpath) => relpath,
+        Err(_) => return Ok(()),
+    };
+    let mut commit = match repo.head_commit() {
+        Ok(commit) => commit,
+        Err(_) => return Ok(()),
+    };
+    // Follows first parents only, like `git log --first-parent`
+    for _ in 0..OWNER_DEPTH {
+        let blob = match blob_at(&commit, relpath)? {
+            Some(blob) => blob,
+            None => break,
+        };
+        let parent = match commit.parent_ids().next() {
+            Some(id) => Some(id.object()?.try_into_commit()?),
+            None => None,
+        };
+        let unchanged = match &parent {
+            Some(parent) => blob_at(parent, relpath)? == Some(blob),
+            None => false,
+        };
+        if !unchanged {
+            write!(stream, "{}", commit.author()?.name.to_string().dim())?;
+            break;
+        }
+        match parent {
+            Some(parent) => commit = parent,
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+/// Commits counted by `Field::GitCount` before giving up and showing `N+`
+const COUNT_DEPTH: usize = 10000;
+
+/// The `Field::GitCount` contents: `⟳N` for the first-parent commits of HEAD, only counting those not on the
+/// `git_count_base` branch (e.g. `main`) when it is set
+pub fn print_count<T: Write>(stream: &mut T, dir: &Path, config: &Config) -> Result<()> {
+    let repo = match gix::discover(dir) {
+        Ok(repo) => repo,
+        Err(_) => return Ok(()),
+    };
+    let head = match repo.head_id() {
+        Ok(head) => head.detach(),
+        Err(_) => return Ok(()),
+    };
+    let base: std::collections::HashSet<_> = match config.get("git_count_base") {
+        Some(branch) => match repo.try_find_reference(branch)? {
+            Some(mut reference) => first_parents(&repo, reference.peel_to_id_in_place()?.detach(), COUNT_DEPTH)?.into_iter().collect(),
+            None => return Ok(()),
+        },
+        None => Default::default(),
+    };
+    let commits = first_parents(&repo, head, COUNT_DEPTH + 1)?;
+    let count = commits.iter().take_while(|&id| !base.contains(id)).count();
+    if count > COUNT_DEPTH {
+        write!(stream, "{}", format_args!("⟳{}+", crate::locale_number(COUNT_DEPTH, config)).cyan())?;
+    } else {
+        write!(stream, "{}", format_args!("⟳{}", crate::locale_number(count, config)).cyan())?;
+    }
+    Ok(())
+}
+
+/// Behind counts from which `Field::GitBehind` is shown in yellow
+const BEHIND_WARNING: usize = 50;
+
+/// The `Field::GitBehind` contents: `⇣N` for the commits on the `git_default_branch` (preferring its `origin` copy)
+/// since it and HEAD diverged, following first parents for at most `COUNT_DEPTH` commits. Nothing is shown on the
+/// default branch itself or when the divergence point can't be found.
+pub fn print_behind<T: Write>(stream: &mut T, dir: &Path, config: &Config) -> Result<()> {
+    let repo = match gix::discover(dir) {
+        Ok(repo) => repo,
+        Err(_) => return Ok(()),
+    };
+    let default_branch = config.get("git_default_branch").unwrap_or("main");
+    let head = repo.head().context("trying to get HEAD")?;
+    if head.referent_name().map(|name| name.shorten() == default_branch).unwrap_or(false) {
+        return Ok(());
+    }
+    let head = match head.id() {
+        Some(head) => head.detach(),
+        None => return Ok(()),
+    };
+    let mut tip = None;
+    for name in [format!("origin/{}", default_branch), default_branch.to_string()].iter() {
+        if let Some(mut reference) = repo.try_find_reference(name.as_str())? {
+            tip = Some(reference.peel_to_id_in_place()?.detach());
+            break;
+        }
+    }
+    let tip = match tip {
+        Some(tip) => tip,
+        None => return Ok(()),
+    };
+    let ours: std::collections::HashSet<_> = first_parents(&repo, head, COUNT_DEPTH)?.into_iter().collect();
+    let behind = match first_parents(&repo, tip, COUNT_DEPTH)?.iter().position(|id| ours.contains(id)) {
+        Some(behind) => behind,
+        None => return Ok(()),
+    };
+    let text = format!("⇣{}", behind);
+    if behind >= BEHIND_WARNING {
+        write!(stream, "{}", text.yellow())?;
+    } else {
+        write!(stream, "{}", text.dim())?;
+    }
+    Ok(())
+}
+
+/// How many commits the current branch is ahead of and behind its configured upstream (`branch.<name>.remote` and
+/// `.merge`), following first parents for at most `COUNT_DEPTH` commits. `None` without an upstream, or when the
+/// two haven't diverged within that depth.
+fn upstream_divergence(repo: &gix::Repository) -> Result<Option<(usize, usize)>> {
+    let head = repo.head().context("trying to get HEAD")?;
+    let (branch, head) = match (head.referent_name(), head.id()) {
+        (Some(name), Some(id)) => (name.shorten().to_string(), id.detach()),
+        _ => return Ok(None),
+    };
+    let snapshot = repo.config_snapshot();
+    let (remote, merge) = match (snapshot.string(format!("branch.{}.remote", branch).as_str()), snapshot.string(format!("branch.{}.merge", branch).as_str())) {
+        (Some(remote), Some(merge)) => (remote.to_string(), merge.to_string()),
+        _ => return Ok(None),
+    };
+    let merge = merge.strip_prefix("refs/heads/").unwrap_or(&merge);
+    // A remote of `.` tracks a local branch
+    let upstream = if remote == "." { format!("refs/heads/{}", merge) } else { format!("refs/remotes/{}/{}", remote, merge) };
+    let upstream = match repo.try_find_reference(upstream.as_str())? {
+        Some(mut reference) => reference.peel_to_id_in_place()?.detach(),
+        None => return Ok(None),
+    };
+    let ours = first_parents(repo, head, COUNT_DEPTH)?;
+    let theirs = first_parents(repo, upstream, COUNT_DEPTH)?;
+    let their_ids: std::collections::HashSet<_> = theirs.iter().collect();
+    let ahead = match ours.iter().position(|id| their_ids.contains(id)) {
+        Some(ahead) => ahead,
+        None => return Ok(None),
+    };
+    Ok(theirs.iter().position(|id| *id == ours[ahead]).map(|behind| (ahead, behind)))
+}
+
+/// The nearest tag on the first-parent history of commit `id` within `COUNT_DEPTH` commits, and how many commits
+/// past it `id` is. Of several tags on one commit, the first by name wins. History is only read up to that tag.
+fn describe(repo: &gix::Repository, mut id: gix::ObjectId) -> Result<Option<(String, usize)>> {
+    let mut tags = std::collections::HashMap::new();
+    for reference in repo.references()?.tags()? {
+        let mut reference = reference.map_err(|e| anyhow::anyhow!(e))?;
+        let name = reference.name().shorten().to_string();
+        let tagged = reference.peel_to_id_in_place()?.detach();
+        let first = tags.entry(tagged).or_insert_with(|| name.clone());
+        if name < *first {
+            *first = name;
+        }
+    }
+    if tags.is_empty() {
+        return Ok(None);
+    }
+    for distance in 0..COUNT_DEPTH {
+        if let Some(name) = tags.get(&id) {
+            return Ok(Some((name.clone(), distance)));
+        }
+        match repo.find_object(id)?.try_into_commit()?.parent_ids().next() {
+            Some(parent) => id = parent.detach(),
+            None => break,
+        }
+    }
+    Ok(None)
+}
+
+/// Roughly how many commits `git bisect` has left to test, the log2 of the commits reachable from `refs/bisect/bad`
+/// but not from any `refs/bisect/good-*`, or `None` until both are marked or if there are more than `COUNT_DEPTH`
+fn bisect_steps(repo: &gix::Repository) -> Result<Option<u32>> {
+    const BAD: u8 = 1;
+    const GOOD: u8 = 2;
+    let mut flags = std::collections::HashMap::new();
+    match repo.try_find_reference("refs/bisect/bad")? {
+        Some(mut bad) => flags.insert(bad.peel_to_id_in_place()?.detach(), BAD),
+        None => return Ok(None),
+    };
+    for reference in repo.references()?.prefixed("refs/bisect/")? {
+        let mut reference = reference.map_err(|e| anyhow::anyhow!(e))?;
+        if reference.name().as_bstr().starts_with(b"refs/bisect/good-") {
+            flags.insert(reference.peel_to_id_in_place()?.detach(), GOOD);
+        }
+    }
+    if !flags.values().any(|flag| *flag == GOOD) {
+        return Ok(None);
+    }
+    // Walking newest first, a commit's children have all been seen by the time it's reached, so once everything
+    // left to walk is reachable from a good commit, every candidate has been counted
+    let time = |id: gix::ObjectId| -> Result<i64> { Ok(repo.find_object(id)?.try_into_commit()?.time()?.seconds) };
+    let mut queue = flags.keys().map(|id| Ok((time(*id)?, *id))).collect::<Result<std::collections::BinaryHeap<_>>>()?;
+    let mut seen = std::collections::HashSet::new();
+    let mut candidates = 0usize;
+    while queue.iter().any(|(_, id)| flags[id] & GOOD == 0) {
+        let (_, id) = queue.pop().expect("the queue has an unmarked commit");
+        if !seen.insert(id) {
+            continue;
+        }
+        if seen.len() > COUNT_DEPTH {
+            return Ok(None);
+        }
+        let flag = flags[&id];
+        if flag == BAD {
+            candidates += 1;
+        }
+        for parent in repo.find_object(id)?.try_into_commit()?.parent_ids() {
+            let parent = parent.detach();
+            let parent_flag = flags.entry(parent).or_insert(0);
+            if *parent_flag | flag != *parent_flag {
+                *parent_flag |= flag;
+                queue.push((time(parent)?, parent));
+            }
+        }
+    }
+    Ok(Some(usize::BITS - candidates.saturating_sub(1).leading_zeros()))
+}
+
+/// Up to `limit` commits following first parents from `id`, starting with `id` itself
+fn first_parents(repo: &gix::Repository, mut id: gix::ObjectId, limit: usize) -> Result<Vec<gix::ObjectId>> {
+    let mut ids = Vec::new();
+    while ids.len() < limit {
+        ids.push(id);
+        match repo.find_object(id)?.try_into_commit()?.parent_ids().next() {
+            Some(parent) => id = parent.detach(),
+            None => break,
+        }
+    }
+    Ok(ids)
+}
+
+/// Whether `git_dir` has an executable pre-commit hook, optionally one generated by the pre-commit framework
+fn has_pre_commit_hook(git_dir: &Path, framework: bool) -> bool {
+    let hook = git_dir.join("hooks").join("pre-commit");
+    // Windows has no executable bit, git runs any hook there
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    let metadata = match std::fs::metadata(&hook) {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => return false,
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 == 0 {
+            return false;
+        }
+    }
+    !framework || std::fs::read_to_string(&hook).is_ok_and(|script| script.contains("pre-commit"))
+}
+
+/// Whether anything is staged or modified, and whether there are untracked files, like `git status --ignored=no`.
+/// The scan stops as soon as both are known.
+fn worktree_state(repo: &gix::Repository) -> Result<(bool, bool)> {
+    use gix::status::index_worktree::iter::Item;
+    let index = repo.index_or_empty()?;
+    let mut changed = match repo.head_tree_id() {
+        Ok(tree) => {
+            let head = repo.index_from_tree(&tree)?;
+            head.entries().len() != index.entries().len()
+                || head.entries().iter().zip(index.entries()).any(|(h, i)| h.id != i.id || h.mode != i.mode || h.path(&head) != i.path(&index))
+        }
+        // Nothing committed yet, so anything in the index is staged
+        Err(_) => !index.entries().is_empty(),
+    };
+    let mut untracked = false;
+    let items = repo.status(gix::progress::Discard)?
+        .index_worktree_submodules(None)
+        .into_index_worktree_iter(Vec::new())?;
+    for item in items {
+        match item? {
+            Item::DirectoryContents { entry, .. } => untracked |= entry.status == gix::dir::entry::Status::Untracked,
+            Item::Modification { .. } | Item::Rewrite { .. } => changed = true,
+        }
+        if changed && untracked {
+            break;
+        }
+    }
+    Ok((changed, untracked))
+}
+
+/// Whether `refs/notes/commits` has a note for HEAD, stored either flat or fanned out (`ab/cdef…`)
+fn has_notes(repo: &gix::Repository) -> Result<bool> {
+    let (mut notes, head) = match (repo.try_find_reference("refs/notes/commits")?, repo.head_id()) {
+        (Some(notes), Ok(head)) => (notes, head.to_string()),
+        _ => return Ok(false),
+    };
+    let tree = notes.peel_to_id_in_place()?.object()?.try_into_commit()?.tree()?;
+    let mut buf = Vec::new();
+    let (fanout, rest) = head.split_at(2);
+    Ok(tree.lookup_entry_by_path(&head, &mut buf)?.is_some() || tree.lookup_entry_by_path(Path::new(fanout).join(rest), &mut buf)?.is_some())
+}
+
+/// How many entries of the stash reflog were made on a base (the stash commit's first parent) that isn't on the
+/// first-parent history of HEAD within `COUNT_DEPTH` commits
+fn foreign_stashes(repo: &gix::Repository) -> Result<usize> {
+    let (stash, head) = match (repo.try_find_reference("refs/stash")?, repo.head_id()) {
+        (Some(stash), Ok(head)) => (stash, head.detach()),
+        _ => return Ok(0),
+    };
+    let ours: std::collections::HashSet<_> = first_parents(repo, head, COUNT_DEPTH)?.into_iter().collect();
+    let mut log = stash.log_iter();
+    let mut count = 0;
+    for line in log.all()?.into_iter().flatten() {
+        let base = repo.find_object(line?.new_oid())?.try_into_commit()?.parent_ids().next().map(|id| id.detach());
+        if base.is_some_and(|base| !ours.contains(&base)) {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// The id of the blob at `path` in `commit`'s tree, if any
+fn blob_at(commit: &gix::Commit<'_>, path: &Path) -> Result<Option<gix::ObjectId>> {
+    let mut buf = Vec::new();
+    Ok(commit.tree()?.lookup_entry_by_path(path, &mut buf)?.map(|entry| entry.object_id()))
+}
+
+/// The `Field::GitRemote` contents: a green dot when `origin` was last found reachable and a red one when it wasn't,
+/// according to the `git_reachable_cache` file kept up to date by a background job, as `url = 1` or `url = 0` lines.
+/// The prompt itself never touches the network, so nothing is shown without a cache entry for `origin`.
+pub fn print_reachable<T: Write>(stream: &mut T, dir: &Path, config: &Config) -> Result<()> {
+    match cached_for_origin(dir, config, "git_reachable_cache", "reachable")?.as_deref() {
+        Some("1") => write!(stream, "{}", (if crate::colors::is_plain() { "reachable" } else { "●" }).green())?,
+        Some(_) => write!(stream, "{}", (if crate::colors::is_plain() { "unreachable" } else { "●" }).red())?,
+        None => {}
+    }
+    Ok(())
+}
+
+/// The `Field::GitConfig` contents: the value of the `git_config_key` git config key (e.g. `user.email`) in the
+/// repository containing `dir`, or nothing when the key is unset
+pub fn print_config<T: Write>(stream: &mut T, dir: &Path, config: &Config) -> Result<()> {
+    let key = match config.get("git_config_key") {
+        Some(key) => key,
+        None => return Ok(()),
+    };
+    let repo = match gix::discover(dir) {
+        Ok(repo) => repo,
+        Err(_) => return Ok(()),
+    };
+    if let Some(value) = repo.config_snapshot().string(key) {
+        write!(stream, "{}", value.to_string().blue())?;
+    }
+    Ok(())
+}
+
+/// The `Field::GitAlias` contents: `alias→expansion` when the shell's `last_cmd` (e.g. `git co main`) ran a git alias
+/// defined for the repository containing `dir`
+pub fn print_alias<T: Write>(stream: &mut T, dir: &Path, config: &Config) -> Result<()> {
+    let mut words = config.get("last_cmd").unwrap_or_default().split_whitespace();
+    let alias = match (words.next(), words.next()) {
+        (Some("git"), Some(alias)) => alias,
+        _ => return Ok(()),
+    };
+    let repo = match gix::discover(dir) {
+        Ok(repo) => repo,
+        Err(_) => return Ok(()),
+    };
+    if let Some(expansion) = repo.config_snapshot().string(format!("alias.{}", alias).as_str()) {
+        write!(stream, "{}", format_args!("{}→{}", alias, expansion).dim())?;
+    }
+    Ok(())
+}
+
+/// The `Field::GitPrs` contents: `⇅N PRs` open against `origin`, from `url = N` lines in the `git_prs_cache` file
+/// kept up to date by a background `gh`/`glab` job
+pub fn print_prs<T: Write>(stream: &mut T, dir: &Path, config: &Config) -> Result<()> {
+    if let Some(count) = cached_for_origin(dir, config, "git_prs_cache", "prs")? {
+        let count: u64 = count.parse().with_context(|| format!("parsing PR count {:?}", count))?;
+        write!(stream, "{}", format_args!("⇅{} PRs", count).cyan())?;
+    }
+    Ok(())
+}
+
+/// The `Field::GitSignature` contents: 🔏 in green when HEAD has a good signature, red for a bad one and yellow for
+/// none. Checking a signature runs `git verify-commit`, so good verdicts are cached by commit in `git_signature_cache`
+/// (`$XDG_CACHE_HOME/omniprompt/signatures`). Bad ones are checked again each time, as trusting the key fixes them.
+pub fn print_signature<T: Write>(stream: &mut T, dir: &Path, config: &Config) -> Result<()> {
+    let repo = match gix::discover(dir) {
+        Ok(repo) => repo,
+        Err(_) => return Ok(()),
+    };
+    let commit = match repo.head_commit() {
+        Ok(commit) => commit,
+        Err(_) => return Ok(()),
+    };
+    let plain = crate::colors::is_plain();
+    if commit.decode()?.extra_headers().pgp_signature().is_none() {
+        write!(stream, "{}", (if plain { "unsigned" } else { "🔏" }).yellow())?;
+        return Ok(());
+    }
+    let id = commit.id.to_string();
+    let cache = config.get("git_signature_cache").map(PathBuf::from).or_else(|| dirs::cache_dir().map(|d| d.join("omniprompt").join("signatures")));
+    let cached = cache.as_ref()
+        .and_then(|cache| std::fs::read_to_string(cache).ok())
+        .and_then(|contents| crate::config::parse_file(&contents).ok())
+        .and_then(|verdicts| verdicts.get(&id).cloned());
+    let good = match cached {
+        Some(verdict) if verdict == "good" => true,
+        _ => {
+            let good = std::process::Command::new("git")
+                .args(["verify-commit", &id])
+                .current_dir(dir)
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .status()
+                .context("running git verify-commit")?
+                .success();
+            // A lost verdict only costs another verification
+            if let Some(cache) = cache.filter(|_| good) {
+                let verdict = format!("{} = good\n", id);
+                let _ = cache.parent().map_or(Ok(()), std::fs::create_dir_all).and_then(|_| {
+                    std::fs::OpenOptions::new().create(true).append(true).open(&cache)?.write_all(verdict.as_bytes())
+                });
+            }
+            good
+        }
+    };
+    if good {
+        write!(stream, "{}", (if plain { "signed" } else { "🔏" }).green())?;
+    } else {
+        write!(stream, "{}", (if plain { "bad signature" } else { "🔏" }).red())?;
+    }
+    Ok(())
+}
+
+/// The entry for the `origin` URL of the repository containing `dir` in a `url = value` cache file, read from the
+/// `option` path or `$XDG_CACHE_HOME/omniprompt/<name>`
+fn cached_for_origin(dir: &Path, config: &Config, option: &str, name: &str) -> Result<Option<String>> {
+    let repo = match gix::discover(dir) {
+        Ok(repo) => repo,
+        Err(_) => return Ok(None),
+    };
+    let url = match repo.config_snapshot().string("remote.origin.url") {
+        Some(url) => url.to_string(),
+        None => return Ok(None),
+    };
+    let cache = match config.get(option).map(PathBuf::from).or_else(|| dirs::cache_dir().map(|d| d.join("omniprompt").join(name))) {
+        Some(cache) => cache,
+        None => return Ok(None),
+    };
+    let contents = match std::fs::read_to_string(&cache) {
+        Ok(contents) => contents,
+        Err(_) => return Ok(None),
+    };
+    let mut entries = crate::config::parse_file(&contents).with_context(|| format!("parsing {}", cache.display()))?;
+    Ok(entries.remove(&url))
+}
+
+/// `value` in the color named by the `key` option, or in `default` when it is unset or isn't a color
+fn paint(config: &Config, key: &str, default: &str, value: impl std::fmt::Display) -> String {
+    config.get(key)
+        .and_then(|name| crate::colors::by_name(name, &value))
+        .or_else(|| crate::colors::by_name(default, &value))
+        .unwrap_or_else(|| value.to_string())
+}
+
+/// The root of the working tree containing `dir`, if any
+pub fn workdir(dir: &Path) -> Option<PathBuf> {
+    gix::discover(dir).ok()?.work_dir().map(Path::to_path_buf)
+}
+
+/// Whether `branch` is fully matched by the `git_protected` regex, e.g. `main|release/.*`
+fn is_protected_branch(branch: &str, pattern: &str) -> Result<bool> {
+    let re = regex::Regex::new(&format!("^(?:{})$", pattern)).context("parsing git_protected")?;
+    Ok(re.is_match(branch))
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::config::Layer;
+    use std::process::Command;
+
+    /// Run `git` in `dir` with a fixed identity, panicking on failure
+    pub fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com", "-c", "commit.gpgsign=false"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .expect("running git");
+        assert!(output.status.success(), "git {:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    /// A fresh repository on `main` with a single commit
+    pub fn repo() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        git(dir.path(), &["init", "-q", "-b", "main"]);
+        git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "initial"]);
+        dir
+    }
+
+    pub fn render(dir: &Path, options: &[(&str, &str)]) -> String {
+        render_field(print, dir, options)
+    }
+
+    /// Render one of this module's fields with the given options
+    fn render_field(print: fn(&mut Vec<u8>, &Path, &Config) -> Result<()>, dir: &Path, options: &[(&str, &str)]) -> String {
+        let flags: Layer = options.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let mut out = Vec::new();
+        print(&mut out, dir, &Config::new(Layer::new(), Default::default(), flags)).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn protected() {
+        assert!(is_protected_branch("main", "main|release/.*").unwrap());
+        assert!(is_protected_branch("release/1.0", "main|release/.*").unwrap());
+        assert!(!is_protected_branch("feature/foo", "main|release/.*").unwrap());
+        assert!(!is_protected_branch("maintenance", "main|release/.*").unwrap());
+        assert!(is_protected_branch("main", "(").is_err());
+    }
+
+    #[test]
+    fn state_colors() {
+        let dir = repo();
+        assert!(render(dir.path(), &[]).contains("\x1b[33mmain"));
+        assert!(render(dir.path(), &[("git_color_branch", "magenta")]).contains("\x1b[35mmain"));
+        assert!(render(dir.path(), &[("git_color_branch", "chartreuse")]).contains("\x1b[33mmain"));
+        assert!(render(dir.path(), &[("git_protected", "main"), ("git_color_protected", "blue")]).contains("\x1b[34mmain"));
+        git(dir.path(), &["checkout", "-q", "--detach"]);
+        let sha = git(dir.path(), &["rev-parse", "--short", "HEAD"]);
+        assert!(render(dir.path(), &[("git_color_detached", "cyan")]).contains(&format!("\x1b[36m{}", sha.trim())));
+
+        std::fs::write(dir.path().join("new.txt"), "new").unwrap();
+        let dirty = [("git_dirty", "1"), ("git_color_dirty", "magenta")];
+        assert!(render(dir.path(), &dirty).ends_with("\x1b[35m+\x1b[39m"));
+        assert!(render(dir.path(), &[dirty[0], ("git_color_dirty", "chartreuse")]).ends_with("\x1b[33m+\x1b[39m"));
+        git(dir.path(), &["add", "new.txt"]);
+        assert!(render(dir.path(), &dirty).ends_with("\x1b[35m*\x1b[39m"));
+    }
+
+    #[test]
+    fn detached() {
+        let dir = repo();
+        assert!(!render(dir.path(), &[]).contains("DETACHED"));
+        git(dir.path(), &["checkout", "-q", "--detach"]);
+        let sha = git(dir.path(), &["rev-parse", "--short", "HEAD"]);
+        let rendered = render(dir.path(), &[]);
+        assert!(rendered.contains("⚠DETACHED"));
+        assert!(rendered.contains(sha.trim()));
+        assert!(!render(dir.path(), &[("git_detached_warning", "0")]).contains("DETACHED"));
+    }
+
+    #[test]
+    fn author_check() {
+        let dir = repo();
+        git(dir.path(), &["config", "user.email", "test@example.com"]);
+        let options = [("git_author_check", "1")];
+        assert!(!render(dir.path(), &options).contains("author"));
+        git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "theirs", "--author", "Other <other@example.com>"]);
+        assert!(render(dir.path(), &options).contains("≠author"));
+        assert!(!render(dir.path(), &[]).contains("author"));
+    }
+
+    #[test]
+    fn notes() {
+        let dir = repo();
+        let options = [("git_notes", "1")];
+        assert!(!render(dir.path(), &options).contains("📝"));
+        git(dir.path(), &["notes", "add", "-m", "reviewed"]);
+        assert!(render(dir.path(), &options).contains("📝"));
+        assert!(!render(dir.path(), &[]).contains("📝"));
+        git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "unreviewed"]);
+        assert!(!render(dir.path(), &options).contains("📝"));
+    }
+
+    #[test]
+    fn sparse() {
+        let dir = repo();
+        assert!(!render(dir.path(), &[]).contains("sparse"));
+        git(dir.path(), &["sparse-checkout", "set", "src"]);
+        assert!(render(dir.path(), &[]).contains("\x1b[2msparse"));
+        git(dir.path(), &["sparse-checkout", "disable"]);
+        assert!(!render(dir.path(), &[]).contains("sparse"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hooks() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = repo();
+        let hook = dir.path().join(".git").join("hooks").join("pre-commit");
+        assert!(render(dir.path(), &[("git_hooks", "1")]).contains("\x1b[31mhooks✗"));
+        std::fs::write(&hook, "#!/bin/sh\nmake lint\n").unwrap();
+        assert!(render(dir.path(), &[("git_hooks", "1")]).contains("hooks✗"));
+        std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(render(dir.path(), &[("git_hooks", "1")]).contains("\x1b[2mhooks✓"));
+        assert!(render(dir.path(), &[("git_hooks", "framework")]).contains("hooks✗"));
+        std::fs::write(&hook, "#!/usr/bin/env bash\n# File generated by pre-commit: https://pre-commit.com\n").unwrap();
+        assert!(render(dir.path(), &[("git_hooks", "framework")]).contains("hooks✓"));
+        assert!(!render(dir.path(), &[]).contains("hooks"));
+        assert!(!render(dir.path(), &[("git_hooks", "0")]).contains("hooks"));
+    }
+
+    #[test]
+    fn dirty() {
+        let dir = repo();
+        let options = [("git_dirty", "1")];
+        assert!(render(dir.path(), &options).ends_with("main\x1b[39m"), "{:?}", render(dir.path(), &options));
+        std::fs::write(dir.path().join("new.txt"), "new").unwrap();
+        assert!(render(dir.path(), &options).ends_with("\x1b[33m+\x1b[39m"));
+        assert!(!render(dir.path(), &options).contains('*'));
+        git(dir.path(), &["add", "new.txt"]);
+        assert!(render(dir.path(), &options).ends_with("\x1b[33m*\x1b[39m"));
+        git(dir.path(), &["commit", "-q", "-m", "new"]);
+        assert!(!render(dir.path(), &options).contains('*'));
+        std::fs::write(dir.path().join("new.txt"), "changed").unwrap();
+        std::fs::write(dir.path().join("other.txt"), "other").unwrap();
+        assert!(render(dir.path(), &options).contains("*\x1b[39m\x1b[33m+"));
+        assert!(!render(dir.path(), &[]).contains('*'));
+    }
+
+    #[test]
+    fn ref_type() {
+        let dir = repo();
+        let options = [("git_ref_type", "1")];
+        assert!(render(dir.path(), &options).starts_with("\x1b[2m⎇\x1b[22m\x1b[33mmain"));
+        git(dir.path(), &["tag", "-a", "-m", "release", "v1.0"]);
+        git(dir.path(), &["tag", "light"]);
+        git(dir.path(), &["checkout", "-q", "v1.0"]);
+        let rendered = render(dir.path(), &options);
+        assert!(rendered.starts_with("\x1b[2m🏷\x1b[22m\x1b[33mlight"), "{:?}", rendered);
+        assert!(!rendered.contains("DETACHED"));
+        assert!(render(dir.path(), &[]).contains("DETACHED"));
+        git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "untagged"]);
+        let rendered = render(dir.path(), &options);
+        assert!(rendered.starts_with("\x1b[1m\x1b[31m⚠"), "{:?}", rendered);
+        assert!(!rendered.contains("DETACHED"));
+    }
+
+    #[test]
+    fn upstream() {
+        let dir = repo();
+        let options = [("git_upstream", "1")];
+        git(dir.path(), &["checkout", "-q", "-b", "feature", "--track", "main"]);
+        assert!(!render(dir.path(), &options).contains('↑'));
+        git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "one"]);
+        git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "two"]);
+        assert!(render(dir.path(), &options).ends_with("feature\x1b[39m \x1b[36m↑2\x1b[39m"));
+        git(dir.path(), &["checkout", "-q", "main"]);
+        git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "three"]);
+        git(dir.path(), &["checkout", "-q", "feature"]);
+        assert!(render(dir.path(), &options).contains("↑2↓1"));
+        assert!(render(dir.path(), &[options[0], ("git_color_diverged", "magenta")]).ends_with("\x1b[35m↑2↓1\x1b[39m"));
+        assert!(render(dir.path(), &[options[0], ("git_color_diverged", "chartreuse")]).ends_with("\x1b[36m↑2↓1\x1b[39m"));
+        assert!(!render(dir.path(), &[]).contains('↑'));
+
+        // A remote-tracking upstream, and none at all
+        git(dir.path(), &["update-ref", "refs/remotes/origin/feature", "HEAD~1"]);
+        git(dir.path(), &["config", "branch.feature.remote", "origin"]);
+        git(dir.path(), &["config", "branch.feature.merge", "refs/heads/feature"]);
+        assert!(render(dir.path(), &options).ends_with("↑1\x1b[39m"));
+        git(dir.path(), &["config", "--unset", "branch.feature.remote"]);
+        assert!(!render(dir.path(), &options).contains('↑'));
+    }
+
+    #[test]
+    fn state() {
+        let dir = repo();
+        let git_dir = dir.path().join(".git");
+        assert!(!render(dir.path(), &[]).contains('|'));
+        for (marker, state) in [("MERGE_HEAD", "MERGE"), ("CHERRY_PICK_HEAD", "CHERRY-PICK"), ("BISECT_LOG", "BISECT"), ("REVERT_HEAD", "REVERT")].iter() {
+            std::fs::write(git_dir.join(marker), "").unwrap();
+            assert!(render(dir.path(), &[]).ends_with(&format!("main\x1b[39m\x1b[1m\x1b[31m|{}\x1b[39m\x1b[22m", state)));
+            std::fs::remove_file(git_dir.join(marker)).unwrap();
+        }
+        std::fs::write(git_dir.join("MERGE_HEAD"), "").unwrap();
+        assert!(render(dir.path(), &[("git_color_operation", "magenta")]).ends_with("\x1b[1m\x1b[35m|MERGE\x1b[39m\x1b[22m"));
+        assert!(render(dir.path(), &[("git_color_operation", "chartreuse")]).ends_with("\x1b[1m\x1b[31m|MERGE\x1b[39m\x1b[22m"));
+        std::fs::remove_file(git_dir.join("MERGE_HEAD")).unwrap();
+
+        // A real rebase stopped by a conflict, on a detached HEAD
+        std::fs::write(dir.path().join("file"), "base").unwrap();
+        git(dir.path(), &["add", "file"]);
+        git(dir.path(), &["commit", "-q", "-m", "base"]);
+        git(dir.path(), &["checkout", "-q", "-b", "topic"]);
+        std::fs::write(dir.path().join("file"), "topic").unwrap();
+        git(dir.path(), &["commit", "-q", "-am", "topic"]);
+        git(dir.path(), &["checkout", "-q", "main"]);
+        std::fs::write(dir.path().join("file"), "main").unwrap();
+        git(dir.path(), &["commit", "-q", "-am", "main"]);
+        git(dir.path(), &["checkout", "-q", "topic"]);
+        let rebase = Command::new("git").args(["-c", "user.name=Test", "-c", "user.email=test@example.com", "rebase", "-q", "main"]).current_dir(dir.path()).output().unwrap();
+        assert!(!rebase.status.success());
+        let rendered = render(dir.path(), &[]);
+        assert!(rendered.contains("DETACHED") && rendered.contains("|REBASE"), "{:?}", rendered);
+    }
+
+    #[test]
+    fn bisect_progress() {
+        let dir = repo();
+        for i in 0..8 {
+            git(dir.path(), &["commit", "-q", "--allow-empty", "-m", &i.to_string()]);
+        }
+        git(dir.path(), &["bisect", "start"]);
+        assert!(render(dir.path(), &[]).ends_with("|BISECT\x1b[39m\x1b[22m"));
+        git(dir.path(), &["bisect", "bad", "HEAD"]);
+        assert!(render(dir.path(), &[]).ends_with("|BISECT\x1b[39m\x1b[22m"));
+        // Eight commits are left between the good and bad ones, counting the bad one
+        git(dir.path(), &["bisect", "good", "HEAD~8"]);
+        assert!(render(dir.path(), &[]).ends_with("|BISECT ~3 left\x1b[39m\x1b[22m"), "{:?}", render(dir.path(), &[]));
+        git(dir.path(), &["bisect", "good"]);
+        assert!(render(dir.path(), &[]).ends_with("|BISECT ~2 left\x1b[39m\x1b[22m"), "{:?}", render(dir.path(), &[]));
+        git(dir.path(), &["bisect", "reset"]);
+        assert!(!render(dir.path(), &[]).contains("BISECT"));
+    }
+
+    #[test]
+    fn describe_detached() {
+        let dir = repo();
+        git(dir.path(), &["tag", "-a", "-m", "release", "v1.2.0"]);
+        git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "one"]);
+        git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "two"]);
+        git(dir.path(), &["checkout", "-q", "--detach"]);
+        let short = git(dir.path(), &["rev-parse", "--short", "HEAD"]);
+        assert!(render(dir.path(), &[]).contains(&format!("v1.2.0-2-g{}", short.trim())));
+        git(dir.path(), &["checkout", "-q", "v1.2.0"]);
+        assert!(render(dir.path(), &[]).ends_with("\x1b[33mv1.2.0\x1b[39m"));
+        git(dir.path(), &["checkout", "-q", "main"]);
+        assert!(render(dir.path(), &[]).contains("main"));
+
+        // Sitting on the tag reads no history, so even a missing parent doesn't matter
+        git(dir.path(), &["tag", "v1.3.0"]);
+        git(dir.path(), &["checkout", "-q", "v1.3.0"]);
+        let parent = git(dir.path(), &["rev-parse", "HEAD~1"]);
+        let (fanout, rest) = parent.trim().split_at(2);
+        std::fs::remove_file(dir.path().join(".git").join("objects").join(fanout).join(rest)).unwrap();
+        assert!(render(dir.path(), &[]).ends_with("\x1b[33mv1.3.0\x1b[39m"), "{:?}", render(dir.path(), &[]));
+    }
+
+    #[test]
+    fn signature() {
+        let dir = repo();
+        let keys = tempfile::tempdir().unwrap();
+        let cache = keys.path().join("signatures");
+        let keygen = |name: &str| {
+            let key = keys.path().join(name);
+            assert!(Command::new("ssh-keygen").args(["-q", "-t", "ed25519", "-N", "", "-C", name, "-f"]).arg(&key).status().unwrap().success());
+            key
+        };
+        let (trusted, untrusted) = (keygen("trusted"), keygen("untrusted"));
+        let public = std::fs::read_to_string(trusted.with_extension("pub")).unwrap();
+        std::fs::write(keys.path().join("allowed"), format!("test@example.com {}", public)).unwrap();
+        git(dir.path(), &["config", "gpg.format", "ssh"]);
+        git(dir.path(), &["config", "gpg.ssh.allowedSignersFile", keys.path().join("allowed").to_str().unwrap()]);
+        let options = [("git_signature_cache", cache.to_str().unwrap())];
+        let render = || render_field(print_signature, dir.path(), &options);
+
+        assert!(render().contains("\x1b[33m🔏"));
+        git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "signed", &format!("--gpg-sign={}", trusted.display())]);
+        assert!(render().contains("\x1b[32m🔏"));
+        git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "forged", &format!("--gpg-sign={}", untrusted.display())]);
+        assert!(render().contains("\x1b[31m🔏"));
+        assert_eq!(std::fs::read_to_string(&cache).unwrap().lines().count(), 1);
+        // Bad verdicts aren't kept, so trusting the key shows up right away
+        let untrusted_public = std::fs::read_to_string(untrusted.with_extension("pub")).unwrap();
+        std::fs::write(keys.path().join("allowed"), format!("test@example.com {}test@example.com {}", public, untrusted_public)).unwrap();
+        assert!(render().contains("\x1b[32m🔏"));
+        // Good verdicts are reused without verifying again
+        git(dir.path(), &["config", "gpg.ssh.allowedSignersFile", "/nonexistent"]);
+        git(dir.path(), &["checkout", "-q", "HEAD~1"]);
+        assert!(render().contains("\x1b[32m🔏"));
+        assert_eq!(std::fs::read_to_string(&cache).unwrap().lines().count(), 2);
+        assert_eq!(render_field(print_signature, keys.path(), &options), "");
+    }
+
+    #[test]
+    fn stash_other() {
+        let dir = repo();
+        let options = [("git_stash_other", "1")];
+        std::fs::write(dir.path().join("file"), "main").unwrap();
+        git(dir.path(), &["add", "file"]);
+        git(dir.path(), &["commit", "-q", "-m", "file"]);
+        std::fs::write(dir.path().join("file"), "main stash").unwrap();
+        git(dir.path(), &["stash", "-q"]);
+        assert!(!render(dir.path(), &options).contains("⚑"));
+
+        git(dir.path(), &["checkout", "-q", "-b", "topic"]);
+        git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "topic"]);
+        std::fs::write(dir.path().join("file"), "topic stash").unwrap();
+        git(dir.path(), &["stash", "-q"]);
+        // The main stash's base is still in topic's history
+        assert!(!render(dir.path(), &options).contains("⚑"));
+        git(dir.path(), &["checkout", "-q", "main"]);
+        assert!(render(dir.path(), &options).contains("\x1b[33m⚑(other:1)"));
+        assert!(!render(dir.path(), &[]).contains("⚑"));
+        git(dir.path(), &["stash", "drop", "-q"]);
+        assert!(!render(dir.path(), &options).contains("⚑"));
+    }
+
+    #[test]
+    fn lock() {
+        let dir = repo();
+        assert!(!render(dir.path(), &[("git_lock", "1")]).contains("locked"));
+        std::fs::write(dir.path().join(".git").join("index.lock"), "").unwrap();
+        assert!(render(dir.path(), &[("git_lock", "1")]).contains("\x1b[31m🔒locked"));
+        assert!(!render(dir.path(), &[]).contains("locked"));
+    }
+
+    #[test]
+    fn reachable() {
+        let dir = repo();
+        let cache = dir.path().join(".git").join("reachable");
+        let cache_option = [("git_reachable_cache", cache.to_str().unwrap())];
+        let render_reachable = || {
+            let flags: Layer = cache_option.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            let mut out = Vec::new();
+            print_reachable(&mut out, dir.path(), &Config::new(Layer::new(), Default::default(), flags)).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        std::fs::write(&cache, "https://example.com/up.git = 1\nhttps://example.com/down.git = 0\n").unwrap();
+        assert_eq!(render_reachable(), "");
+        git(dir.path(), &["remote", "add", "origin", "https://example.com/up.git"]);
+        assert!(render_reachable().contains("\x1b[32m●"));
+        git(dir.path(), &["remote", "set-url", "origin", "https://example.com/down.git"]);
+        assert!(render_reachable().contains("\x1b[31m●"));
+        git(dir.path(), &["remote", "set-url", "origin", "https://example.com/unknown.git"]);
+        assert_eq!(render_reachable(), "");
+        std::fs::remove_file(&cache).unwrap();
+        assert_eq!(render_reachable(), "");
+    }
+
+    #[test]
+    fn config() {
+        let dir = repo();
+        git(dir.path(), &["config", "omniprompt.test", "hello"]);
+        assert!(render_field(print_config, dir.path(), &[("git_config_key", "omniprompt.test")]).contains("hello"));
+        assert_eq!(render_field(print_config, dir.path(), &[("git_config_key", "omniprompt.missing")]), "");
+        assert_eq!(render_field(print_config, dir.path(), &[]), "");
+    }
+
+    #[test]
+    fn alias() {
+        let dir = repo();
+        git(dir.path(), &["config", "alias.co", "checkout"]);
+        assert!(render_field(print_alias, dir.path(), &[("last_cmd", "git co main")]).contains("co→checkout"));
+        assert_eq!(render_field(print_alias, dir.path(), &[("last_cmd", "git checkout main")]), "");
+        assert_eq!(render_field(print_alias, dir.path(), &[("last_cmd", "co main")]), "");
+        assert_eq!(render_field(print_alias, dir.path(), &[]), "");
+    }
+
+    #[test]
+    fn prs() {
+        let dir = repo();
+        let cache = dir.path().join(".git").join("prs");
+        let render_prs = || {
+            let flags = Layer::from([("git_prs_cache".to_string(), cache.to_str().unwrap().to_string())]);
+            let mut out = Vec::new();
+            print_prs(&mut out, dir.path(), &Config::new(Layer::new(), Default::default(), flags)).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        git(dir.path(), &["remote", "add", "origin", "git@example.com:team/repo.git"]);
+        assert_eq!(render_prs(), "");
+        std::fs::write(&cache, "git@example.com:team/other.git = 7\n").unwrap();
+        assert_eq!(render_prs(), "");
+        std::fs::write(&cache, "git@example.com:team/other.git = 7\ngit@example.com:team/repo.git = 3\n").unwrap();
+        assert!(render_prs().contains("⇅3 PRs"));
+    }
+
+    #[test]
+    fn behind() {
+        let dir = repo();
+        git(dir.path(), &["checkout", "-q", "-b", "feature"]);
+        git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "feature work"]);
+        git(dir.path(), &["checkout", "-q", "main"]);
+        for message in ["one", "two", "three"] {
+            git(dir.path(), &["commit", "-q", "--allow-empty", "-m", message]);
+        }
+        assert_eq!(render_field(print_behind, dir.path(), &[]), "");
+        git(dir.path(), &["checkout", "-q", "feature"]);
+        let rendered = render_field(print_behind, dir.path(), &[]);
+        assert!(rendered.contains("⇣3") && rendered.contains("\x1b[2m"), "{:?}", rendered);
+        assert_eq!(render_field(print_behind, dir.path(), &[("git_default_branch", "trunk")]), "");
+        git(dir.path(), &["checkout", "-q", "main"]);
+        for i in 0..BEHIND_WARNING {
+            git(dir.path(), &["commit", "-q", "--allow-empty", "-m", &i.to_string()]);
+        }
+        git(dir.path(), &["checkout", "-q", "feature"]);
+        assert!(render_field(print_behind, dir.path(), &[]).contains(&format!("\x1b[33m⇣{}", BEHIND_WARNING + 3)));
+    }
+
+    #[test]
+    fn count() {
+        let dir = repo();
+        git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "second"]);
+        git(dir.path(), &["checkout", "-q", "-b", "feature"]);
+        for message in ["third", "fourth", "fifth"] {
+            git(dir.path(), &["commit", "-q", "--allow-empty", "-m", message]);
+        }
+        let render_count = |options: &[(&str, &str)]| {
+            let flags: Layer = options.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            let mut out = Vec::new();
+            print_count(&mut out, dir.path(), &Config::new(Layer::new(), Default::default(), flags)).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert!(render_count(&[]).contains("⟳5"));
+        assert!(render_count(&[("git_count_base", "main")]).contains("⟳3"));
+        assert_eq!(render_count(&[("git_count_base", "missing")]), "");
+    }
+
+    #[test]
+    fn owner() {
+        let dir = repo();
+        let render_owner = |dir: &Path| {
+            let mut out = Vec::new();
+            print_owner(&mut out, dir, &Config::default()).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(render_owner(dir.path()), "");
+        std::fs::write(dir.path().join("README.md"), "hello\n").unwrap();
+        git(dir.path(), &["add", "README.md"]);
+        git(dir.path(), &["-c", "user.name=Owner", "commit", "-q", "-m", "readme"]);
+        git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "unrelated"]);
+        let rendered = render_owner(dir.path());
+        assert!(rendered.contains("Owner"), "{}", rendered);
+        assert!(!rendered.contains("Test"));
+        let subdir = dir.path().join("sub");
+        std::fs::create_dir(&subdir).unwrap();
+        assert_eq!(render_owner(&subdir), "");
+    }
+}
@@ -3,49 +3,149 @@ use chrono::Local;
 use core::str::FromStr;
 use std::io::Write;
 use std::net::IpAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+mod config;
+#[cfg(feature="git")]
+mod git;
+
+use config::Config;
 
 mod colors {
+    use std::cell::Cell;
     use std::fmt::Display;
+    #[cfg(unix)]
     use std::path::Path;
+    #[cfg(unix)]
     use std::ffi::OsStr;
 
     thread_local! {
-        static ESCAPES: (&'static str, &'static str) = {
+        static ESCAPES: Cell<(&'static str, &'static str)> = Cell::new(parent_shell().as_deref().and_then(shell_escapes).unwrap_or(("","")));
+        static PLAIN: Cell<bool> = const { Cell::new(false) };
+        static CHOICE: Cell<Choice> = const { Cell::new(Choice::Always) };
+        static HINT: Cell<Option<&'static str>> = const { Cell::new(None) };
+    }
+
+    /// The name of the parent process, usually the shell the prompt is rendered for
+    pub fn parent_shell() -> Option<String> {
+        #[cfg(unix)]
+        {
             let ppid = std::os::unix::process::parent_id();
-            Path::new(&format!("/proc/{ppid}/exe"))
-                .read_link()
-                .ok()
-                .and_then(|p| {
-                    if p.file_name() == Some(OsStr::new("zsh")) {
-                        Some(("\x25\x7b", "\x25\x7d"))
-                    } else if p.file_name() == Some(OsStr::new("bash")) {
-                        Some((r#"\["#, r#"\]"#))
-                    } else {
-                        None
-                    }
-                })
-                .unwrap_or(("",""))
+            if let Ok(exe) = Path::new(&format!("/proc/{ppid}/exe")).read_link() {
+                return exe.file_name().and_then(OsStr::to_str).map(shell_name);
+            }
+        }
+        // Without procfs, e.g. on macOS, the BSDs and Windows, the kernel is asked through sysinfo
+        #[cfg(any(feature="sysinfo", not(unix)))]
+        {
+            let ppid = super::parent_pid()?;
+            let mut system = sysinfo::System::new();
+            system.refresh_processes_specifics(sysinfo::ProcessesToUpdate::Some(&[ppid]), sysinfo::ProcessRefreshKind::new());
+            system.process(ppid)?.name().to_str().map(shell_name)
+        }
+        #[cfg(not(any(feature="sysinfo", not(unix))))]
+        None
+    }
+
+    /// `process_name` without the `-` login shells are started with or Windows' `.exe`, e.g. `zsh` for `-zsh`
+    pub fn shell_name(process_name: &str) -> String {
+        process_name.trim_start_matches('-').trim_end_matches(".exe").to_string()
+    }
+
+    /// The markers `shell` needs around the non-printing parts of its prompt, or `None` for shells omniprompt doesn't know
+    pub fn shell_escapes(shell: &str) -> Option<(&'static str, &'static str)> {
+        match shell {
+            "zsh" => Some(("\x25\x7b", "\x25\x7d")),
+            "bash" => Some((r#"\["#, r#"\]"#)),
+            // fish and PowerShell work out the prompt's width themselves, skipping over escapes
+            "fish" | "powershell" | "pwsh" | "none" => Some(("", "")),
+            _ => None,
+        }
+    }
+
+    /// Override the markers detected from the parent process, once before rendering
+    pub fn set_escapes(escapes: (&'static str, &'static str)) {
+        ESCAPES.with(|e| e.set(escapes));
+    }
+
+    pub fn escapes() -> (&'static str, &'static str) {
+        ESCAPES.with(Cell::get)
+    }
+
+    /// When to emit color escapes
+    #[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+    pub enum Choice {
+        /// Even when not writing to a terminal, as shells capture the prompt through a pipe
+        #[default]
+        Always,
+        Never,
+        /// Only when stdout supports color
+        Auto,
+    }
+
+    /// Set when to emit color escapes, once before rendering
+    pub fn set_choice(choice: Choice) {
+        CHOICE.with(|c| c.set(choice));
+    }
+
+    pub fn choice() -> Choice {
+        CHOICE.with(Cell::get)
+    }
+
+    /// Whether colors are written as escapes, per the `Choice` and `set_plain`
+    pub fn escapes_enabled() -> bool {
+        !is_plain() && match CHOICE.with(Cell::get) {
+            Choice::Always => true,
+            Choice::Never => false,
+            Choice::Auto => supports_color::on_cached(supports_color::Stream::Stdout).is_some(),
         }
     }
 
+    /// Remember `color` as the hint for what's being rendered if it says more than the current one: red (trouble)
+    /// over yellow (a warning) over whatever came first. Bold isn't a color.
+    fn note_color(color: &'static str) {
+        let rank = |color: Option<&str>| match color {
+            None | Some("bold") => 0,
+            Some("red") => 3,
+            Some("yellow") => 2,
+            Some(_) => 1,
+        };
+        HINT.with(|hint| {
+            if rank(Some(color)) > rank(hint.get()) {
+                hint.set(Some(color));
+            }
+        });
+    }
+
+    /// The color hint noted since the last call, whether or not escapes were emitted
+    pub fn take_hint() -> Option<&'static str> {
+        HINT.with(|hint| hint.take())
+    }
+
+    /// Render colors as their contents alone, e.g. for screen readers
+    pub fn set_plain(plain: bool) {
+        PLAIN.with(|p| p.set(plain));
+    }
+
+    pub fn is_plain() -> bool {
+        PLAIN.with(Cell::get)
+    }
+
     macro_rules! def_colors {
         ($($color_name:ident | $color_name_lower: ident => ($color:literal, $reset:literal)),+) => {
             $(
+                #[allow(dead_code)]
                 pub struct $color_name<T: Display>(T);
 
                 impl<T: Display> Display for $color_name<T> {
                     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-                        ESCAPES.with(|(escape_begin, escape_end)| {
-                            write!(f, concat!("{}", "\x1b[", $color, "m", "{}{}{}", "\x1b[", $reset, "m", "{}"), escape_begin, escape_end, self.0, escape_begin, escape_end)
-                            /*
-                            if supports_color::on_cached(supports_color::Stream::Stdout).is_some() {
-                                write!(f, concat!("{}", "\x1b[", $color, "m", "{}{}{}", "\x1b[", $reset, "m", "{}"), escape_begin, escape_end, self.0, escape_begin, escape_end)
-                            } else {
-                                self.0.fmt(f)
-                            }
-                            */
-                        })
+                        note_color(stringify!($color_name_lower));
+                        if !escapes_enabled() {
+                            return self.0.fmt(f);
+                        }
+                        let (escape_begin, escape_end) = escapes();
+                        write!(f, concat!("{}", "\x1b[", $color, "m", "{}{}{}", "\x1b[", $reset, "m", "{}"), escape_begin, escape_end, self.0, escape_begin, escape_end)
                     }
                 }
             )+
@@ -53,6 +153,7 @@ mod colors {
             pub trait Colorizer {
                 type Target: Display;
                 $(
+                    #[allow(dead_code)]
                     fn $color_name_lower(self) -> $color_name<Self::Target>;
                 )+
             }
@@ -69,6 +170,80 @@ mod colors {
         }
     }
 
+    /// 24-bit foreground color, for terminals with truecolor support
+    pub struct Rgb<T: Display>(pub (u8, u8, u8), pub T);
+
+    impl<T: Display> Display for Rgb<T> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            if !escapes_enabled() {
+                return self.1.fmt(f);
+            }
+            let (r, g, b) = self.0;
+            let (escape_begin, escape_end) = escapes();
+            write!(f, "{}\x1b[38;2;{};{};{}m{}{}{}\x1b[39m{}", escape_begin, r, g, b, escape_end, self.1, escape_begin, escape_end)
+        }
+    }
+
+    /// `value` in the color called `name` (e.g. `magenta`), or `None` for unknown names
+    pub fn by_name<T: Display>(name: &str, value: T) -> Option<String> {
+        Some(match name {
+            "red" => value.red().to_string(),
+            "green" => value.green().to_string(),
+            "yellow" => value.yellow().to_string(),
+            "blue" => value.blue().to_string(),
+            "magenta" => value.magenta().to_string(),
+            "cyan" => value.cyan().to_string(),
+            "dim" => value.dim().to_string(),
+            _ => return None,
+        })
+    }
+
+    /// `sequence` marked as non-printing for the shell, so it doesn't count towards the prompt's width
+    pub fn non_printing(sequence: &str) -> String {
+        let (escape_begin, escape_end) = escapes();
+        format!("{}{}{}", escape_begin, sequence, escape_end)
+    }
+
+    /// The start of a 256-color background that foreground resets leave in place, for banding whole rows
+    pub fn background(index: u8) -> String {
+        if escapes_enabled() { non_printing(&format!("\x1b[48;5;{}m", index)) } else { String::new() }
+    }
+
+    pub fn background_reset() -> String {
+        if escapes_enabled() { non_printing("\x1b[49m") } else { String::new() }
+    }
+
+    /// `s` without color escapes or the shell's non-printing markers, i.e. only what the terminal displays
+    pub fn strip(s: &str) -> String {
+        let (escape_begin, escape_end) = escapes();
+        let s = if escape_begin.is_empty() { s.to_string() } else { s.replace(escape_begin, "").replace(escape_end, "") };
+        let mut visible = String::with_capacity(s.len());
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                match chars.next() {
+                    // CSI sequences end at the first byte in `@`..=`~`
+                    Some('[') => {
+                        chars.by_ref().find(|c| ('@'..='~').contains(c));
+                    }
+                    // OSC sequences end at BEL, or at ST (`ESC \`) whose `\` is dropped along with it
+                    Some(']') if chars.by_ref().find(|c| *c == '\x07' || *c == '\x1b') == Some('\x1b') => {
+                        chars.next();
+                    }
+                    _ => {}
+                }
+            } else {
+                visible.push(c);
+            }
+        }
+        visible
+    }
+
+    /// The terminal cells `s` takes up once stripped, counting wide characters such as CJK and emoji as two
+    pub fn width(s: &str) -> usize {
+        unicode_width::UnicodeWidthStr::width(strip(s).as_str())
+    }
+
     def_colors! {
         Bold | bold => (1, 22),
         Red | red => (31, 39),
@@ -76,7 +251,8 @@ mod colors {
         Yellow | yellow => (33, 39),
         Blue | blue => (34, 39),
         Magenta | magenta => (35, 39),
-        Cyan | cyan => (36, 39)
+        Cyan | cyan => (36, 39),
+        Dim | dim => (2, 22)
     }
 
 }
@@ -95,80 +271,608 @@ macro_rules! let_workaround {
 }
 
 struct FieldWriter<T: Write> {
+    /// Whether the current row's `row_bands` background is still to be closed
+    band_open: bool,
     column_count: usize,
+    config: Config,
+    /// When fields still rendering stop being waited for, per `deadline_ms`
+    deadline: Option<std::time::Instant>,
     errors: String,
     exit_code: Option<i32>,
+    /// Where each field's contents ended up, as byte ranges of what was written, and their display width
+    offsets: Vec<(Field, std::ops::Range<usize>, usize)>,
+    /// The fields rendered so far, for `format = json`
+    records: Vec<JsonRecord>,
     row_count: usize,
+    /// Visible columns written on the current row
+    row_width: usize,
     stream: T,
+    written: usize,
+}
+
+/// What a field renders to, before it's framed into a section or reported as JSON
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+struct FieldValue {
+    /// The field's text, with color escapes unless colors are off
+    text: String,
+    /// The color that best sums the field up, e.g. `red` for a failed command (see `colors::take_hint`)
+    color: Option<String>,
+}
+
+/// A field as reported by `format = json`
+#[derive(serde::Serialize)]
+struct JsonRecord {
+    name: &'static str,
+    value: String,
+    color: Option<String>,
+    error: Option<String>,
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 enum Field {
+    #[cfg(feature="asdf")]
+    Asdf,
+    #[cfg(feature="network")]
+    Bandwidth,
+    #[cfg(all(feature="battery", target_os="linux"))]
+    Battery,
+    #[cfg(all(feature="network", target_os="linux"))]
+    Connections,
+    Duration,
     ExitCode,
+    File,
     #[cfg(feature="git")]
     Git,
+    #[cfg(feature="git")]
+    GitAlias,
+    #[cfg(feature="git")]
+    GitBehind,
+    #[cfg(feature="git")]
+    GitConfig,
+    #[cfg(feature="git")]
+    GitCount,
+    #[cfg(feature="git")]
+    GitOwner,
+    #[cfg(feature="git")]
+    GitPrs,
+    #[cfg(feature="git")]
+    GitRemote,
+    #[cfg(feature="git")]
+    GitSignature,
+    #[cfg(all(feature="platform", unix))]
+    Groups,
     #[cfg(feature="network")]
     Network,
+    HistNum,
+    #[cfg(feature="kube")]
+    Kube,
+    #[cfg(feature="load")]
+    Load,
+    #[cfg(unix)]
+    Mail,
+    #[cfg(feature="memory")]
+    Memory,
+    #[cfg(feature="mise")]
+    Mise,
+    #[cfg(target_os="linux")]
+    Mount,
+    OldPwd,
+    #[cfg(feature="patch")]
+    Patch,
     #[cfg(feature="platform")]
     Platform,
     Ppid,
     Prompt,
     Pwd,
+    #[cfg(all(feature="platform", unix))]
+    ReadOnly,
+    Sandbox,
+    #[cfg(all(feature="security", target_os="linux"))]
+    SecurityContext,
+    SessionTime,
+    Stdin,
+    #[cfg(all(feature="systemd", target_os="linux"))]
+    Systemd,
+    #[cfg(feature="platform")]
+    Temp,
     Time,
     #[cfg(feature="tty")]
     Tty,
     Whoami,
+    Wsl,
+}
+
+impl Field {
+    /// How the field is announced in `accessible` mode, if at all
+    fn label(self) -> Option<&'static str> {
+        match self {
+            #[cfg(feature="asdf")]
+            Field::Asdf => Some("tools"),
+            #[cfg(feature="network")]
+            Field::Bandwidth => Some("bandwidth"),
+            #[cfg(all(feature="battery", target_os="linux"))]
+            Field::Battery => Some("battery"),
+            #[cfg(all(feature="network", target_os="linux"))]
+            Field::Connections => Some("connections"),
+            Field::Duration => Some("duration"),
+            Field::ExitCode => Some("exit code"),
+            Field::File => Some("file"),
+            #[cfg(feature="git")]
+            Field::Git => Some("branch"),
+            #[cfg(feature="git")]
+            Field::GitAlias => Some("alias"),
+            #[cfg(feature="git")]
+            Field::GitBehind => Some("behind"),
+            #[cfg(feature="git")]
+            Field::GitConfig => Some("git config"),
+            #[cfg(feature="git")]
+            Field::GitCount => Some("commits"),
+            #[cfg(feature="git")]
+            Field::GitOwner => Some("owner"),
+            #[cfg(feature="git")]
+            Field::GitPrs => Some("pull requests"),
+            #[cfg(feature="git")]
+            Field::GitRemote => Some("remote"),
+            #[cfg(feature="git")]
+            Field::GitSignature => Some("signature"),
+            #[cfg(all(feature="platform", unix))]
+            Field::Groups => Some("group"),
+            #[cfg(feature="network")]
+            Field::Network => Some("network"),
+            Field::HistNum => Some("history"),
+            #[cfg(feature="kube")]
+            Field::Kube => Some("kubernetes"),
+            #[cfg(feature="load")]
+            Field::Load => Some("load"),
+            #[cfg(feature="patch")]
+            Field::Patch => Some("patch"),
+            #[cfg(unix)]
+            Field::Mail => Some("mail"),
+            #[cfg(feature="memory")]
+            Field::Memory => Some("memory"),
+            #[cfg(feature="mise")]
+            Field::Mise => Some("mise"),
+            #[cfg(target_os="linux")]
+            Field::Mount => Some("network mount"),
+            Field::OldPwd => Some("previous directory"),
+            #[cfg(feature="platform")]
+            Field::Platform => Some("platform"),
+            Field::Ppid => Some("parent process"),
+            Field::Prompt => None,
+            Field::Pwd => Some("directory"),
+            #[cfg(all(feature="platform", unix))]
+            Field::ReadOnly => Some("read-only"),
+            Field::Sandbox => Some("sandbox"),
+            #[cfg(all(feature="security", target_os="linux"))]
+            Field::SecurityContext => Some("security context"),
+            Field::SessionTime => Some("session"),
+            Field::Stdin => Some("stdin"),
+            #[cfg(all(feature="systemd", target_os="linux"))]
+            Field::Systemd => Some("systemd unit"),
+            #[cfg(feature="platform")]
+            Field::Temp => Some("temperature"),
+            Field::Time => Some("time"),
+            #[cfg(feature="tty")]
+            Field::Tty => Some("terminal"),
+            Field::Whoami => Some("user"),
+            Field::Wsl => Some("WSL"),
+        }
+    }
+
+    /// The field's name in the `fields` option, JSON output and `color_<field>`, e.g. `exit_code`
+    fn key(self) -> &'static str {
+        match self {
+            #[cfg(feature="asdf")]
+            Field::Asdf => "asdf",
+            #[cfg(feature="network")]
+            Field::Bandwidth => "bandwidth",
+            #[cfg(all(feature="battery", target_os="linux"))]
+            Field::Battery => "battery",
+            #[cfg(all(feature="network", target_os="linux"))]
+            Field::Connections => "connections",
+            Field::Duration => "duration",
+            Field::ExitCode => "exit_code",
+            Field::File => "file",
+            #[cfg(feature="git")]
+            Field::Git => "git",
+            #[cfg(feature="git")]
+            Field::GitAlias => "git_alias",
+            #[cfg(feature="git")]
+            Field::GitBehind => "git_behind",
+            #[cfg(feature="git")]
+            Field::GitConfig => "git_config",
+            #[cfg(feature="git")]
+            Field::GitCount => "git_count",
+            #[cfg(feature="git")]
+            Field::GitOwner => "git_owner",
+            #[cfg(feature="git")]
+            Field::GitPrs => "git_prs",
+            #[cfg(feature="git")]
+            Field::GitRemote => "git_remote",
+            #[cfg(feature="git")]
+            Field::GitSignature => "git_signature",
+            #[cfg(all(feature="platform", unix))]
+            Field::Groups => "groups",
+            Field::HistNum => "histnum",
+            #[cfg(feature="kube")]
+            Field::Kube => "kube",
+            #[cfg(feature="load")]
+            Field::Load => "load",
+            #[cfg(unix)]
+            Field::Mail => "mail",
+            #[cfg(feature="memory")]
+            Field::Memory => "memory",
+            #[cfg(feature="mise")]
+            Field::Mise => "mise",
+            #[cfg(target_os="linux")]
+            Field::Mount => "mount",
+            #[cfg(feature="network")]
+            Field::Network => "network",
+            Field::OldPwd => "oldpwd",
+            #[cfg(feature="patch")]
+            Field::Patch => "patch",
+            #[cfg(feature="platform")]
+            Field::Platform => "platform",
+            Field::Ppid => "ppid",
+            Field::Prompt => "prompt",
+            Field::Pwd => "pwd",
+            #[cfg(all(feature="platform", unix))]
+            Field::ReadOnly => "read_only",
+            Field::Sandbox => "sandbox",
+            #[cfg(all(feature="security", target_os="linux"))]
+            Field::SecurityContext => "security_context",
+            Field::SessionTime => "session_time",
+            Field::Stdin => "stdin",
+            #[cfg(all(feature="systemd", target_os="linux"))]
+            Field::Systemd => "systemd",
+            #[cfg(feature="platform")]
+            Field::Temp => "temp",
+            Field::Time => "time",
+            #[cfg(feature="tty")]
+            Field::Tty => "tty",
+            Field::Whoami => "whoami",
+            Field::Wsl => "wsl",
+        }
+    }
+
+    /// The field whose `key` is `name`, e.g. `exit_code`
+    fn from_name(name: &str) -> Result<Self> {
+        // Fields compiled out are named in the error rather than reported as unknown
+        macro_rules! gated {
+            ($cfg:meta, $requirement:literal, $field:ident) => {{
+                #[cfg($cfg)]
+                return Ok(Field::$field);
+                #[cfg(not($cfg))]
+                return Err(anyhow!("field {:?} needs {}", name, $requirement));
+            }};
+        }
+        Ok(match name {
+            "asdf" => gated!(feature="asdf", "the asdf feature", Asdf),
+            "bandwidth" => gated!(feature="network", "the network feature", Bandwidth),
+            "battery" => gated!(all(feature="battery", target_os="linux"), "the battery feature on Linux", Battery),
+            "connections" => gated!(all(feature="network", target_os="linux"), "the network feature on Linux", Connections),
+            "duration" => Field::Duration,
+            "exit_code" => Field::ExitCode,
+            "file" => Field::File,
+            "git" => gated!(feature="git", "the git feature", Git),
+            "git_alias" => gated!(feature="git", "the git feature", GitAlias),
+            "git_behind" => gated!(feature="git", "the git feature", GitBehind),
+            "git_config" => gated!(feature="git", "the git feature", GitConfig),
+            "git_count" => gated!(feature="git", "the git feature", GitCount),
+            "git_owner" => gated!(feature="git", "the git feature", GitOwner),
+            "git_prs" => gated!(feature="git", "the git feature", GitPrs),
+            "git_remote" => gated!(feature="git", "the git feature", GitRemote),
+            "git_signature" => gated!(feature="git", "the git feature", GitSignature),
+            "groups" => gated!(all(feature="platform", unix), "the platform feature on Unix", Groups),
+            "histnum" => Field::HistNum,
+            "kube" => gated!(feature="kube", "the kube feature", Kube),
+            "load" => gated!(feature="load", "the load feature", Load),
+            "mail" => gated!(unix, "Unix", Mail),
+            "memory" => gated!(feature="memory", "the memory feature", Memory),
+            "mise" => gated!(feature="mise", "the mise feature", Mise),
+            "mount" => gated!(target_os="linux", "Linux", Mount),
+            "network" => gated!(feature="network", "the network feature", Network),
+            "oldpwd" => Field::OldPwd,
+            "patch" => gated!(feature="patch", "the patch feature", Patch),
+            "platform" => gated!(feature="platform", "the platform feature", Platform),
+            "ppid" => Field::Ppid,
+            "prompt" => Field::Prompt,
+            "pwd" => Field::Pwd,
+            "read_only" => gated!(all(feature="platform", unix), "the platform feature on Unix", ReadOnly),
+            "sandbox" => Field::Sandbox,
+            "security_context" => gated!(all(feature="security", target_os="linux"), "the security feature on Linux", SecurityContext),
+            "session_time" => Field::SessionTime,
+            "stdin" => Field::Stdin,
+            "systemd" => gated!(all(feature="systemd", target_os="linux"), "the systemd feature on Linux", Systemd),
+            "temp" => gated!(feature="platform", "the platform feature", Temp),
+            "time" => Field::Time,
+            "tty" => gated!(feature="tty", "the tty feature", Tty),
+            "whoami" => Field::Whoami,
+            "wsl" => Field::Wsl,
+            _ => return Err(anyhow!("unknown field {:?}", name)),
+        })
+    }
+
+    /// Whether rendering the field forks an external process, which `safe` mode forbids
+    fn spawns_process(self) -> bool {
+        // Every other field makes do with syscalls, file reads and gix
+        #[cfg(feature="git")]
+        if self == Field::GitSignature {
+            return true;
+        }
+        false
+    }
 }
 
 impl<T: Write> FieldWriter<T> {
-    fn new(stream: T, exit_code: Option<i32>) -> Self {
+    fn new(stream: T, exit_code: Option<i32>, config: Config) -> Self {
         Self {
+            band_open: false,
             column_count: 0,
+            config,
+            deadline: None,
             errors: String::new(),
             exit_code,
+            offsets: Vec::new(),
+            records: Vec::new(),
             row_count: 0,
+            row_width: 0,
             stream,
+            written: 0,
         }
     }
 
     fn print_line(&mut self) -> Result<()> {
-        writeln!(self.stream)?;
+        if self.is_iterm_statusbar() || self.is_json() {
+            return Ok(());
+        }
+        // Past `maxrows`, the remaining fields wrap onto the last row instead
+        if let Some(max_rows) = self.config.parse::<usize>("maxrows")? {
+            if self.row_count + 1 >= max_rows {
+                return Ok(());
+            }
+        }
+        self.close_band(true)?;
+        writeln!(self)?;
         self.column_count = 0;
         self.row_count += 1;
         Ok(())
     }
 
-    fn print_field(function: Field, exit_code: Option<i32>, stream: &mut T) -> Result<()> {
-        #[cfg(any(not(unix)))]
-        let si = {
-            use sysinfo::{RefreshKind};
-            let mut rk = RefreshKind::new();
-            {
-                use sysinfo::ProcessRefreshKind;
-                rk = rk.with_processes(ProcessRefreshKind::new());
-            }
-            sysinfo::System::new_with_specifics(rk)
-        };
+    /// Start the current row's background in `row_bands` mode, colored `row_band0` on the first row and `row_bandn`
+    /// on the rest
+    fn open_band(&mut self) -> Result<()> {
+        if !self.config.is_enabled("row_bands") || self.is_json() {
+            return Ok(());
+        }
+        let index = self.config.parse::<u8>(if self.row_count == 0 { "row_band0" } else { "row_bandn" })?.unwrap_or_default();
+        write!(self, "{}", colors::background(index))?;
+        self.band_open = true;
+        Ok(())
+    }
+
+    /// End the current row's background, first padding the row to `$COLUMNS` if `pad` so the band spans the terminal
+    fn close_band(&mut self, pad: bool) -> Result<()> {
+        if !self.band_open {
+            return Ok(());
+        }
+        if let (true, Some(columns)) = (pad, self.config.env("COLUMNS").and_then(|c| c.parse::<usize>().ok())) {
+            write!(self, "{:1$}", "", columns.saturating_sub(self.row_width))?;
+        }
+        write!(self, "{}", colors::background_reset())?;
+        self.band_open = false;
+        Ok(())
+    }
+
+    /// Render `function` on its own, as its text and color hint
+    fn field_value(function: Field, exit_code: Option<i32>, config: &Config) -> Result<FieldValue> {
+        let stream = &mut Vec::new();
+        colors::take_hint();
         match function {
+            #[cfg(feature="asdf")]
+            Field::Asdf => {
+                let cwd = std::env::current_dir()?;
+                if let Some(contents) = cwd.ancestors().find_map(|dir| std::fs::read_to_string(dir.join(".tool-versions")).ok()) {
+                    let summary = tool_versions(&contents);
+                    if !summary.is_empty() {
+                        write!(stream, "{}", summary.green())?;
+                    }
+                }
+            }
+            #[cfg(feature="network")]
+            Field::Bandwidth => {
+                if let Some(path) = config.get("bandwidth_cache").map(PathBuf::from).or_else(bandwidth_cache) {
+                    if let Ok(contents) = std::fs::read_to_string(&path) {
+                        print_bandwidth(stream, &config::parse_file(&contents)?)?;
+                    }
+                }
+            }
+            #[cfg(all(feature="battery", target_os="linux"))]
+            Field::Battery => {
+                if let Some((percent, charging)) = battery(Path::new("/sys/class/power_supply")) {
+                    print_battery(stream, percent, charging)?;
+                }
+            }
+            #[cfg(all(feature="network", target_os="linux"))]
+            Field::Connections => {
+                let tables: Vec<String> = ["/proc/net/tcp", "/proc/net/tcp6"].iter().filter_map(|p| std::fs::read_to_string(p).ok()).collect();
+                if let Some(inodes) = session_sockets().filter(|_| !tables.is_empty()) {
+                    let count: usize = tables.iter().map(|table| established_connections(table, &inodes)).sum();
+                    let text = format!("{} tcp", locale_number(count, config));
+                    if count > config.parse::<usize>("connections_warn")?.unwrap_or(CONNECTIONS_WARN) {
+                        write!(stream, "{}", text.yellow())?;
+                    } else {
+                        write!(stream, "{}", text.dim())?;
+                    }
+                }
+            }
+            Field::Duration => {
+                if let Some(millis) = config.parse::<u64>("duration_ms")? {
+                    let duration = Duration::from_millis(millis);
+                    // Unlike the session's age, a command's duration is worth showing below a second
+                    let format = if config.get("duration_format").is_some() { duration_format(config)? } else { DurationFormat::Auto };
+                    let text = format_duration(duration, format);
+                    if duration > Duration::from_secs(30) {
+                        write!(stream, "{}", text.red())?;
+                    } else if duration > Duration::from_secs(5) {
+                        write!(stream, "{}", text.yellow())?;
+                    } else {
+                        write!(stream, "{}", text.dim())?;
+                    }
+                }
+            }
             Field::ExitCode => {
                 match exit_code {
                     Some(0) => write!(stream, "{}", 0.green().bold())?,
-                    Some(v) => write!(stream, "{}", v.red().bold())?,
+                    Some(v) => match signal_name(v) {
+                        Some(signal) => write!(stream, "{}", format_args!("{} ({})", locale_number(v, config), signal).red().bold())?,
+                        None => write!(stream, "{}", locale_number(v, config).red().bold())?,
+                    },
                     None => {},
                 }
             }
+            Field::File => {
+                if let Some(line) = config.get("file_show").and_then(|path| first_line(Path::new(path))) {
+                    write!(stream, "{}", line.cyan())?;
+                }
+            }
             #[cfg(feature="git")]
             Field::Git => {
-                if let Ok(repo) = gix::discover(".") {
-                    write!(stream, "{}", repo.head().context("trying to get HEAD")?.referent_name().map_or("<UNKNOWN>".into(), |s|s.file_name()).yellow())?;
-                }
+                git::print(stream, Path::new("."), config)?;
+            },
+            #[cfg(feature="git")]
+            Field::GitAlias => {
+                git::print_alias(stream, Path::new("."), config)?;
+            },
+            #[cfg(feature="git")]
+            Field::GitBehind => {
+                git::print_behind(stream, Path::new("."), config)?;
+            },
+            #[cfg(feature="git")]
+            Field::GitConfig => {
+                git::print_config(stream, Path::new("."), config)?;
+            },
+            #[cfg(feature="git")]
+            Field::GitCount => {
+                git::print_count(stream, Path::new("."), config)?;
+            },
+            #[cfg(feature="git")]
+            Field::GitOwner => {
+                git::print_owner(stream, Path::new("."), config)?;
+            },
+            #[cfg(feature="git")]
+            Field::GitPrs => {
+                git::print_prs(stream, Path::new("."), config)?;
+            },
+            #[cfg(feature="git")]
+            Field::GitRemote => {
+                git::print_reachable(stream, Path::new("."), config)?;
+            },
+            #[cfg(feature="git")]
+            Field::GitSignature => {
+                git::print_signature(stream, Path::new("."), config)?;
             },
+            #[cfg(all(feature="platform", unix))]
+            Field::Groups => {
+                write!(stream, "{}", group_name(nix::unistd::getegid()).dim())?;
+                // Apple platforms have no getgroups(2) wrapper
+                #[cfg(not(target_vendor="apple"))]
+                if config.is_enabled("groups_count") {
+                    write!(stream, "{}", format_args!("+{}", nix::unistd::getgroups()?.len()).dim())?;
+                }
+            }
+            Field::HistNum => {
+                if let Some(histnum) = config.parse::<u64>("histnum")? {
+                    write!(stream, "{}", format_args!("!{}", histnum).dim())?;
+                }
+            }
+            #[cfg(feature="kube")]
+            Field::Kube => {
+                let path = match config.env("KUBECONFIG").filter(|p| !p.is_empty()) {
+                    Some(paths) => std::env::split_paths(paths).next(),
+                    None => dirs::home_dir().map(|home| home.join(".kube").join("config")),
+                };
+                if let Some((context, server)) = path.and_then(|p| std::fs::read_to_string(p).ok()).as_deref().and_then(kube_context) {
+                    let shown = match (config.get("kube_show").unwrap_or("context"), server) {
+                        ("server", Some(server)) => server,
+                        ("both", Some(server)) => format!("{}@{}", context, server),
+                        _ => context,
+                    };
+                    write!(stream, "{}", shown.blue())?;
+                }
+            }
+            #[cfg(feature="load")]
+            Field::Load => {
+                #[cfg(unix)]
+                {
+                    let load = sysinfo::System::load_average();
+                    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+                    print_load(stream, [load.one, load.five, load.fifteen], cores)?;
+                }
+            }
+            #[cfg(unix)]
+            Field::Mail => {
+                let mailboxes = match (config.env("MAILPATH"), config.env("MAIL")) {
+                    (Some(mailpath), _) => std::env::split_paths(mailpath).collect(),
+                    (None, Some(mail)) => vec![PathBuf::from(mail)],
+                    (None, None) => Vec::new(),
+                };
+                if has_new_mail(&mailboxes) {
+                    write!(stream, "{}", (if colors::is_plain() { "new" } else { "✉" }).yellow())?;
+                }
+            }
+            #[cfg(feature="memory")]
+            Field::Memory => {
+                use sysinfo::{MemoryRefreshKind, RefreshKind, System};
+                let system = System::new_with_specifics(RefreshKind::new().with_memory(MemoryRefreshKind::new().with_ram()));
+                print_memory(stream, system.used_memory(), system.total_memory(), mem_display(config)?)?;
+            }
+            #[cfg(feature="mise")]
+            Field::Mise => {
+                // Activating mise in a shell exports these, and only then does it also apply plain `.tool-versions`
+                let activated = ["MISE_SHELL", "__MISE_DIFF"].iter().any(|name| config.env(name).is_some_and(|v| !v.is_empty()));
+                if let Some(tools) = mise_tools(&std::env::current_dir()?, activated) {
+                    let summary = tool_summary(&tools);
+                    if !summary.is_empty() {
+                        write!(stream, "{}", summary.green())?;
+                    }
+                }
+            }
+            #[cfg(target_os="linux")]
+            Field::Mount => {
+                if let Ok(mounts) = std::fs::read_to_string("/proc/mounts") {
+                    if let Some(fs_type) = network_mount(&mounts, &std::env::current_dir()?) {
+                        write!(stream, "{}", fs_type.yellow())?;
+                    }
+                }
+            }
+            Field::OldPwd => {
+                if let Some(oldpwd) = config.env("OLDPWD").filter(|d| !d.is_empty()) {
+                    write!(stream, "{}", format_args!("←{}", pwd(Path::new(oldpwd), dirs::home_dir().as_deref(), config)?).dim())?;
+                }
+            }
             #[cfg(feature="network")]
             Field::Network => {
                 use bytesize::ByteSize;
-                let (upload, download) = sysinfo::Networks::new_with_refreshed_list().into_iter().map(|(_, nw)| (ByteSize(nw.received()), ByteSize(nw.transmitted()))).fold((ByteSize(0),ByteSize(0)), |sum,current|(sum.0+current.0, sum.1+current.1));
-                write!(stream, "↑{}↓{}", upload, download)?;
+                let networks = sysinfo::Networks::new_with_refreshed_list();
+                if config.is_enabled("net_cumulative") {
+                    let (upload, download) = network_totals(networks.values().map(|nw| (nw.total_received(), nw.total_transmitted())));
+                    print_network(stream, ByteSize(upload), ByteSize(download), config)?;
+                } else {
+                    let path = config.get("net_state_file").map(PathBuf::from).unwrap_or_else(|| network_state_file(dirs::runtime_dir(), dirs::cache_dir()));
+                    let sample: Vec<(String, u64, u64)> = networks.iter().map(|(name, nw)| (name.clone(), nw.total_received(), nw.total_transmitted())).collect();
+                    if let Some((upload, download)) = network_rates(&path, Local::now().timestamp_millis(), &sample)? {
+                        let rate = |bytes| format!("{}/s", ByteSize(bytes).to_string().replace(' ', ""));
+                        print_network(stream, rate(upload), rate(download), config)?;
+                    }
+                }
             },
+            #[cfg(feature="patch")]
+            Field::Patch => {
+                if let Some(patch) = top_patch(&std::env::current_dir()?) {
+                    write!(stream, "{}", patch.yellow())?;
+                }
+            }
             #[cfg(feature="platform")]
             Field::Platform => {
                 #[cfg(unix)]
@@ -195,15 +899,17 @@ impl<T: Write> FieldWriter<T> {
                         ).red()
                     )?;
                 }
+                // Without CI for it, check this still builds with `cargo check --target x86_64-pc-windows-gnu`
                 #[cfg(not(unix))]
                 if let Some(os_version) = sysinfo::System::os_version() {
+                    // e.g. `Windows 11 (22631)/x86_64`, as sysinfo reports the version as `11 (22631)`
                     write!(
                         stream,
                         "{}",
                         format_args!(
-                            "{} ({})/{}",
-                            sysinfo::System::distribution_id(),
-                            os_version
+                            "{} {}/{}",
+                            sysinfo::System::name().unwrap_or_else(sysinfo::System::distribution_id),
+                            os_version,
                             std::env::consts::ARCH
                         ).red()
                     )?;
@@ -222,10 +928,7 @@ impl<T: Write> FieldWriter<T> {
                 #[cfg(unix)]
                 write!(stream, "{}", std::os::unix::process::parent_id().yellow())?;
                 #[cfg(not(unix))]
-                {
-                    use sysinfo::{ProcessExt, SystemExt};
-                    let pid = sysinfo::get_current_pid().map_err(|e|anyhow!("{}",e))?;
-                    let parent_pid = si.process(pid).ok_or_else(||anyhow!("Couldn't find current PID"))?.parent().ok_or_else(||anyhow!("No parent for current process"))?;
+                if let Some(parent_pid) = parent_pid() {
                     write!(stream, "{}", parent_pid.yellow())?;
                 }
             }
@@ -234,19 +937,90 @@ impl<T: Write> FieldWriter<T> {
             }
             Field::Pwd => {
                 let cwd = std::env::current_dir()?;
-                let final_path = match dirs::home_dir() {
-                    Some(home_dir) => match cwd.strip_prefix(home_dir) {
-                        Ok(relpath) if !relpath.as_os_str().is_empty() => Path::new("~").join(relpath),
-                        Ok(_) => "~".into(),
-                        Err(_) => cwd,
-                    },
-                    None => cwd,
-                };
-                write!(stream, "{}", final_path.display().yellow().bold())?;
+                let home_dir = dirs::home_dir();
+                // `pwd_symlink` shows the path the shell took, followed by where it really leads
+                let logical = if config.is_enabled("pwd_symlink") { logical_pwd(config.env("PWD"), &cwd) } else { None };
+                let pwd = pwd(logical.as_deref().unwrap_or(&cwd), home_dir.as_deref(), config)?;
+                if config.get("pwd_style") == Some("gradient") {
+                    write!(stream, "{}", gradient(&pwd).bold())?;
+                } else {
+                    write!(stream, "{}", pwd.yellow().bold())?;
+                }
+                if logical.is_some() {
+                    let physical = abbreviate(&home_relative(&cwd, home_dir.as_deref()).display().to_string());
+                    write!(stream, "{}", format_args!(" (→ {})", physical).dim())?;
+                }
+            }
+            #[cfg(all(feature="platform", unix))]
+            Field::ReadOnly => {
+                if let Ok(stat) = nix::sys::statvfs::statvfs(&std::env::current_dir()?) {
+                    print_read_only(stream, stat.flags())?;
+                }
+            }
+            Field::Sandbox => {
+                if let Some(sandbox) = sandbox_app(config, std::fs::read_to_string("/.flatpak-info").ok().as_deref()) {
+                    write!(stream, "{}", sandbox.yellow())?;
+                }
+            }
+            #[cfg(all(feature="security", target_os="linux"))]
+            Field::SecurityContext => {
+                if let Some(context) = security_context(&[Path::new("/proc/self/attr/apparmor/current"), Path::new("/proc/self/attr/current")]) {
+                    write!(stream, "{}", context.dim())?;
+                }
+            }
+            Field::SessionTime => {
+                if let Ok(Some(start)) = config.parse::<i64>("session_start") {
+                    let elapsed = Local::now().timestamp().saturating_sub(start).max(0);
+                    write!(stream, "{}", format_duration(Duration::from_secs(elapsed as u64), duration_format(config)?).magenta())?;
+                }
+            }
+            Field::Stdin => {
+                print_stdin(stream, std::io::stdin())?;
+            }
+            #[cfg(all(feature="systemd", target_os="linux"))]
+            Field::Systemd => {
+                if let Some(unit) = std::fs::read_to_string("/proc/self/cgroup").ok().as_deref().and_then(systemd_unit) {
+                    write!(stream, "{}", unit.dim())?;
+                }
+            }
+            #[cfg(feature="platform")]
+            Field::Temp => {
+                let hottest = sysinfo::Components::new_with_refreshed_list()
+                    .iter()
+                    .map(|c| c.temperature())
+                    .filter(|t| t.is_finite())
+                    .fold(None, |hottest: Option<f32>, t| Some(hottest.map_or(t, |h| h.max(t))));
+                if let Some(celsius) = hottest {
+                    print_temperature(stream, celsius)?;
+                }
             }
             Field::Time => {
-                // stream.write_all(Local::now().to_rfc3339().as_bytes())?;
-                write!(stream, "{}", Local::now().format("%Y-%m-%d %H:%M:%S%.3f %Z").magenta())?;
+                let mut time = Vec::new();
+                let now = Local::now();
+                let style = config.get("time_style").unwrap_or("absolute");
+                if style != "ago" {
+                    // stream.write_all(Local::now().to_rfc3339().as_bytes())?;
+                    write!(time, "{}", now.format("%Y-%m-%d %H:%M:%S%.3f %Z").magenta())?;
+                    if config.is_enabled("time_dual") {
+                        write!(time, "{}", format_args!(" ({})", now.with_timezone(&chrono::Utc).format("%H:%M:%SZ")).magenta())?;
+                    }
+                }
+                if style == "ago" || style == "both" {
+                    let elapsed = match config.get("last_prompt_file").map(PathBuf::from).or_else(last_prompt_file) {
+                        Some(path) => since_last_prompt(&path, now.timestamp())?,
+                        None => None,
+                    };
+                    if let Some(elapsed) = elapsed {
+                        if style == "both" {
+                            write!(time, " ")?;
+                        }
+                        write!(time, "{}", format_args!("+{}", format_duration(Duration::from_secs(elapsed), duration_format(config)?)).magenta())?;
+                    }
+                }
+                if let (false, Some(width)) = (time.is_empty(), config.parse::<usize>("time_width")?) {
+                    pad(&mut time, width);
+                }
+                stream.write_all(&time)?;
             }
             #[cfg(feature="tty")]
             Field::Tty => {
@@ -254,139 +1028,2711 @@ impl<T: Write> FieldWriter<T> {
                 write!(stream, "{}", nix::unistd::ttyname(std::io::stdin())?.to_string_lossy().yellow())?;
             }
             Field::Whoami => {
-                let_workaround! {
-                    let first = format_args!(
-                        "{}@{}",
-                        whoami::username().red().bold(),
-                        whoami::fallible::hostname().unwrap_or_else(|_|String::from("???")).red().bold()
-                    );
-                    if let Some(ssh_connection) = std::env::var_os("SSH_CONNECTION") {
-                        let mut pieces = ssh_connection.to_str().ok_or_else(||anyhow!("Invalid UTF-8 for SSH_CONNECTION"))?.split(' ').skip(2);
-                        let ssh_server_ip = IpAddr::from_str(pieces.next().ok_or_else(||anyhow!("Missing server IP"))?)?;
-                        let ssh_server_port = u16::from_str(pieces.next().ok_or_else(||anyhow!("Missing server port"))?)?;
-
-                        write!(stream, "{}", format_args!("{} ({}:{})", first, ssh_server_ip, ssh_server_port).cyan())?;
-                    } else {
-                        write!(stream, "{}", first.cyan())?;
+                let user = whoami::username();
+                let host = whoami::fallible::hostname().unwrap_or_else(|_|String::from("???"));
+                let server = config.env("SSH_CONNECTION").map(ssh_server).transpose()?;
+                if let Some(template) = config.get("whoami_format") {
+                    write!(stream, "{}", whoami_format(template, &user, &host, server).cyan())?;
+                } else {
+                    let_workaround! {
+                        let first = format_args!("{}@{}", user.red().bold(), host.red().bold());
+                        if let Some((ssh_server_ip, ssh_server_port)) = server {
+                            write!(stream, "{}", format_args!("{} ({}:{})", first, ssh_server_ip, ssh_server_port).cyan())?;
+                        } else {
+                            write!(stream, "{}", first.cyan())?;
+                        }
                     }
                 }
             }
+            Field::Wsl => {
+                if let Some(distro) = wsl_distro(config.env("WSL_DISTRO_NAME").map(str::to_string), std::fs::read_to_string("/proc/version").ok()) {
+                    write!(stream, "{}", distro.blue())?;
+                }
+            }
         }
 
-        Ok(())
+        Ok(FieldValue { text: String::from_utf8_lossy(stream).into_owned(), color: colors::take_hint().map(str::to_string) })
+    }
+
+    /// `field_value`, reusing it from the last `cache_ttl_<field>` seconds (e.g. `cache_ttl_platform = 3600`) in the
+    /// same directory. Fields aren't cached by default.
+    fn cached_field_value(function: Field, exit_code: Option<i32>, config: &Config) -> Result<FieldValue> {
+        let name = function.key();
+        let ttl = config.parse::<u64>(&format!("cache_ttl_{}", name))?.unwrap_or(0);
+        let dir = config.get("cache_dir").map(PathBuf::from).or_else(|| dirs::cache_dir().map(|d| d.join("omniprompt").join("fields")));
+        let dir = match dir {
+            Some(dir) if ttl != 0 => dir,
+            _ => return Self::field_value(function, exit_code, config),
+        };
+        // The cached text carries escapes and shell markers, so it's only reused when rendering the same way, and
+        // fields read variables like OLDPWD or KUBECONFIG, so only in the same environment
+        let path = {
+            use std::hash::{Hash, Hasher};
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            std::env::current_dir()?.hash(&mut hasher);
+            exit_code.hash(&mut hasher);
+            (colors::escapes(), colors::escapes_enabled(), colors::is_plain()).hash(&mut hasher);
+            config.options().iter().filter(|(key, _)| !CACHE_VOLATILE.contains(&key.as_str())).for_each(|option| option.hash(&mut hasher));
+            config.environment().hash(&mut hasher);
+            dir.join(format!("{}-{:016x}", name, hasher.finish()))
+        };
+        let age = std::fs::metadata(&path).and_then(|m| m.modified()).ok().and_then(|modified| modified.elapsed().ok());
+        if let (Some(age), Ok(cached)) = (age, std::fs::read(&path)) {
+            // A cache file that doesn't parse, e.g. from an older version, is simply rendered again
+            if let (true, Ok(value)) = (age < Duration::from_secs(ttl), serde_json::from_slice(&cached)) {
+                return Ok(value);
+            }
+        }
+        let value = Self::field_value(function, exit_code, config)?;
+        // A stale or missing cache only costs speed
+        let _ = std::fs::create_dir_all(&dir).and_then(|_| std::fs::write(&path, serde_json::to_vec(&value)?));
+        Ok(value)
+    }
+
+    /// `cached_field_value` on a thread of its own, giving up on it at the `deadline` with the `deadline_placeholder`
+    /// (`…` by default) in its place, so the layout doesn't shift once it finishes in time again
+    fn value_before_deadline(&self, function: Field) -> Result<FieldValue> {
+        let deadline = match self.deadline {
+            Some(deadline) => deadline,
+            None => return Self::cached_field_value(function, self.exit_code, &self.config),
+        };
+        let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+        if !remaining.is_zero() {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            let (exit_code, config, plain, choice, escapes) = (self.exit_code, self.config.clone(), colors::is_plain(), colors::choice(), colors::escapes());
+            std::thread::spawn(move || {
+                colors::set_plain(plain);
+                colors::set_choice(choice);
+                colors::set_escapes(escapes);
+                let _ = sender.send(FieldWriter::<Vec<u8>>::cached_field_value(function, exit_code, &config));
+            });
+            // A field that misses the deadline is left running, as there's no stopping it
+            if let Ok(result) = receiver.recv_timeout(remaining) {
+                return result;
+            }
+        }
+        let placeholder = self.config.get("deadline_placeholder").unwrap_or("…");
+        let text = if placeholder.is_empty() { String::new() } else { placeholder.dim().to_string() };
+        Ok(FieldValue { text, color: None })
     }
 
     fn print_section(&mut self, function: Field) -> Result<()> {
-        if self.column_count != 0 {
-            self.stream.write_all(if self.row_count == 0 { b" - " } else { b"-" })?;
+        if function.spawns_process() && self.config.is_enabled("safe") {
+            return Ok(());
+        }
+        let (mut value, mut result) = match self.value_before_deadline(function) {
+            Ok(value) => (value, Ok(())),
+            Err(e) => (FieldValue::default(), Err(e)),
+        };
+        // `color_<field>` (e.g. `color_exit_code`) repaints the whole field in one of the named colors
+        if let (true, Some(color)) = (result.is_ok() && !value.text.is_empty(), self.config.get(&format!("color_{}", function.key()))) {
+            match colors::by_name(color, colors::strip(&value.text)) {
+                Some(painted) => value = FieldValue { text: painted, color: Some(color.to_string()) },
+                None => result = Err(anyhow!("parsing color_{} {:?}: unknown color", function.key(), color)),
+            }
+        }
+        let contents = value.text.as_bytes();
+        // Fields with nothing to show are skipped entirely rather than rendering as empty brackets
+        if contents.is_empty() && result.is_ok() {
+            return Ok(());
         }
-        write!(self.stream, "{}", (if self.column_count != 0 { "[" } else if self.row_count == 0 { "┌─[" } else { "└─[" }).red().bold())?;
 
-        if let Err(e) = Self::print_field(function, self.exit_code, &mut self.stream) {
-            use std::fmt::Write;
-            if self.errors.is_empty() {
-                write!(self.errors, "{:?}", e)?;
-            } else {
-                write!(self.errors, "\n{:?}", e)?;
+        if self.is_iterm_statusbar() {
+            if let Some(label) = function.label() {
+                let value = colors::strip(&value.text);
+                write!(self, "{}", set_user_var(&format!("omniprompt_{}", label.replace(' ', "_")), &value))?;
             }
+            return self.record_error(result);
         }
+        // Each field's error stays with it rather than joining the error block, and there's no input line. Colors are
+        // off for JSON, so the text is already plain.
+        if self.is_json() {
+            if function != Field::Prompt {
+                let error = result.err().map(|e| format!("{:#}", e));
+                self.records.push(JsonRecord { name: function.key(), value: value.text, color: value.color, error });
+            }
+            return Ok(());
+        }
+        // Uncolored spaces insetting each row from the margin
+        if self.column_count == 0 {
+            self.open_band()?;
+            let indent = self.config.parse::<usize>("indent")?.unwrap_or(0);
+            write!(self, "{:1$}", "", indent)?;
+        }
+        if self.config.is_enabled("accessible") {
+            return self.print_accessible_section(function, contents, result);
+        }
+
+        if self.column_count != 0 {
+            let separator = self.config.get(if self.row_count == 0 { "sep_row0" } else { "sep_rown" }).unwrap_or_default().to_string();
+            self.write_all(separator.as_bytes())?;
+        }
+        let (top, bottom) = if self.config.get("frame") == Some("rounded") { ("╭─[", "╰─[") } else { ("┌─[", "└─[") };
+        write!(self, "{}", (if self.column_count != 0 { "[" } else if self.row_count == 0 { top } else { bottom }).red().bold())?;
+
+        let start = self.written;
+        self.write_all(contents)?;
+        self.offsets.push((function, start..self.written, colors::width(&value.text)));
+        self.record_error(result)?;
         self.column_count += 1;
 
-        write!(self.stream, "{}", (if function != Field::Prompt { "]" } else { "]> " }).red().bold())?;
+        write!(self, "{}", (if function != Field::Prompt { "]" } else { "]> " }).red().bold())?;
 
         Ok(())
     }
 
+    /// Print `fields` flush against the right edge of the current row per `$COLUMNS`, or inline like any other sections
+    /// when the terminal's width is unknown, they don't fit or they'd start the row
+    fn print_right_sections(&mut self, fields: &[Field]) -> Result<()> {
+        let columns = self.config.env("COLUMNS").and_then(|c| c.parse::<usize>().ok());
+        let columns = match columns {
+            Some(columns) if self.column_count != 0 && !self.is_iterm_statusbar() && !self.is_json() && !self.config.is_enabled("accessible") => columns,
+            _ => {
+                for field in fields {
+                    self.print_section(*field)?;
+                }
+                return Ok(());
+            }
+        };
+        // The padding depends on the sections' width, so they're rendered apart first, continuing the row
+        let mut right = FieldWriter::new(Vec::new(), self.exit_code, self.config.clone());
+        right.deadline = self.deadline;
+        right.row_count = self.row_count;
+        right.column_count = 1;
+        for field in fields {
+            right.print_section(*field)?;
+        }
+        if right.stream.is_empty() {
+            return Ok(());
+        }
+        let separator = self.config.get(if self.row_count == 0 { "sep_row0" } else { "sep_rown" }).unwrap_or_default().to_string();
+        let contents = right.stream.strip_prefix(separator.as_bytes()).unwrap_or(&right.stream);
+        let skipped = right.stream.len() - contents.len();
+        let width = colors::width(&String::from_utf8_lossy(contents));
+        match columns.checked_sub(self.row_width + width).filter(|gap| *gap > 0) {
+            Some(gap) => write!(self, "{:1$}", "", gap)?,
+            None => self.write_all(separator.as_bytes())?,
+        }
+        let start = self.written;
+        self.write_all(contents)?;
+        self.offsets.extend(right.offsets.into_iter().map(|(field, range, width)| (field, range.start - skipped + start..range.end - skipped + start, width)));
+        if !right.errors.is_empty() {
+            self.record_error(Err(anyhow!("{}", right.errors)))?;
+        }
+        self.column_count += right.column_count - 1;
+        Ok(())
+    }
+
+    /// A section as `label: contents` without box drawing or icons, for screen readers
+    fn print_accessible_section(&mut self, function: Field, contents: &[u8], result: Result<()>) -> Result<()> {
+        if self.column_count != 0 {
+            self.write_all(b", ")?;
+        }
+        if let Some(label) = function.label() {
+            write!(self, "{}: ", label)?;
+        }
+        let start = self.written;
+        let contents = strip_icons(&String::from_utf8_lossy(contents));
+        write!(self, "{}", contents)?;
+        self.offsets.push((function, start..self.written, colors::width(&contents)));
+        if function == Field::Prompt {
+            self.write_all(b" ")?;
+        }
+        self.record_error(result)?;
+        self.column_count += 1;
+        Ok(())
+    }
+
+    fn record_error(&mut self, result: Result<()>) -> Result<()> {
+        if let Err(e) = result {
+            use std::fmt::Write;
+            if self.errors.is_empty() {
+                write!(self.errors, "{:?}", e)?;
+            } else {
+                write!(self.errors, "\n{:?}", e)?;
+            }
+        }
+        Ok(())
+    }
+
     fn print_errors(&mut self) -> Result<()> {
-        write!(self.stream, "{}", (&self.errors).red().bold())?;
+        if self.is_iterm_statusbar() {
+            write!(self, "{}", set_user_var("omniprompt_errors", &self.errors))?;
+        } else {
+            let errors = self.errors.clone();
+            write!(self, "{}", errors.red().bold())?;
+        }
+        Ok(())
+    }
+
+    /// Whether fields are emitted as iTerm2 user variables for its status bar rather than as a prompt
+    fn is_iterm_statusbar(&self) -> bool {
+        self.config.get("format") == Some("iterm-statusbar")
+    }
+
+    fn is_json(&self) -> bool {
+        is_json(&self.config)
+    }
+
+    fn print_json(&mut self) -> Result<()> {
+        let records = serde_json::to_string(&self.records)?;
+        writeln!(self, "{}", records)?;
         Ok(())
     }
 
     fn has_errors(&self) -> bool {
         !self.errors.is_empty()
     }
+
+    /// An invisible trailer for tools that want field boundaries without parsing escapes: an OSC sequence holding
+    /// a JSON array of `["field", start, end, width]` entries, each a byte range into everything written so far and
+    /// the columns the field takes up on screen
+    fn print_offsets(&mut self) -> Result<()> {
+        let ranges: Vec<String> = self.offsets.iter().map(|(field, range, width)| format!("[\"{}\",{},{},{}]", field.key(), range.start, range.end, width)).collect();
+        let trailer = colors::non_printing(&format!("\x1b]{};[{}]\x07", OFFSETS_OSC, ranges.join(",")));
+        self.write_all(trailer.as_bytes())?;
+        Ok(())
+    }
 }
 
-fn print_default(exit_code: Option<i32>) -> Result<()> {
-    let mut out = [0u8; 2048];
-    let out_len = out.len() - {
-        let mut out_written = &mut out[..];
-        // let stdout = std::io::stdout();
-        // let mut fw = FieldWriter::new(stdout.lock(), exit_code);
-        let mut fw = FieldWriter::new(&mut out_written, exit_code);
-
-        fw.print_section(Field::Whoami)?;
-        fw.print_section(Field::Pwd)?;
-        fw.print_section(Field::Ppid)?;
-        fw.print_section(Field::Time)?;
-        #[cfg(feature="platform")]
-        fw.print_section(Field::Platform)?;
-        #[cfg(feature="network")]
-        fw.print_section(Field::Network)?;
-        fw.print_line()?;
-        fw.print_section(Field::ExitCode)?;
-        #[cfg(feature="git")]
-        fw.print_section(Field::Git)?;
-        if fw.has_errors() {
-            fw.print_line()?;
-            fw.print_errors()?;
-            fw.print_line()?;
+impl<T: Write> Write for FieldWriter<T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.stream.write(buf)?;
+        self.written += written;
+        let text = String::from_utf8_lossy(&buf[..written]);
+        match text.rfind('\n') {
+            Some(newline) => self.row_width = colors::width(&text[newline + 1..]),
+            None => self.row_width += colors::width(&text),
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Whether fields are emitted as a JSON array of `{"name", "value", "color", "error"}` objects rather than as a
+/// prompt, for `format = json` or `--json`
+fn is_json(config: &Config) -> bool {
+    config.get("format") == Some("json") || config.is_enabled("json")
+}
+
+/// Options that change with every prompt, which would keep any field from being cached if they were part of its key
+const CACHE_VOLATILE: &[&str] = &["duration_ms", "histnum", "session_start"];
+
+/// The private OSC code of the `field_offsets` trailer, which terminals ignore
+const OFFSETS_OSC: u32 = 7770;
+
+/// The iTerm2 escape setting the user variable `name` to `value`, which status bar components can show as
+/// `\(user.name)`
+fn set_user_var(name: &str, value: &str) -> String {
+    colors::non_printing(&format!("\x1b]1337;SetUserVar={}={}\x07", name, base64(value.as_bytes())))
+}
+
+/// Standard, padded base64
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// The bytes uploaded and downloaded across interfaces, from each interface's bytes received and transmitted
+#[cfg(feature="network")]
+fn network_totals(interfaces: impl Iterator<Item = (u64, u64)>) -> (u64, u64) {
+    interfaces.fold((0, 0), |(upload, download), (received, transmitted)| (upload + transmitted, download + received))
+}
+
+/// The digit grouping separator of the `LC_ALL`, `LC_NUMERIC` or `LANG` locale when `locale_numbers` is enabled, for
+/// the languages omniprompt knows
+fn grouping_separator(config: &Config) -> Option<char> {
+    if !config.is_enabled("locale_numbers") {
+        return None;
+    }
+    let locale = ["LC_ALL", "LC_NUMERIC", "LANG"].iter().find_map(|name| config.env(name).filter(|v| !v.is_empty()))?;
+    match locale.split(['_', '.', '@']).next()? {
+        "en" | "he" | "ja" | "ko" | "th" | "zh" => Some(','),
+        "da" | "de" | "el" | "es" | "id" | "it" | "nl" | "pt" | "tr" => Some('.'),
+        "cs" | "fi" | "fr" | "hu" | "nb" | "pl" | "ru" | "sk" | "sv" | "uk" => Some('\u{a0}'),
+        _ => None,
+    }
+}
+
+/// `n` with its digits grouped in threes per `grouping_separator`, e.g. `12,345`, or as plain digits
+fn locale_number(n: impl std::fmt::Display, config: &Config) -> String {
+    let number = n.to_string();
+    let separator = match grouping_separator(config) {
+        Some(separator) => separator,
+        None => return number,
+    };
+    let digits = number.trim_start_matches('-');
+    let mut grouped = number[..number.len() - digits.len()].to_string();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(separator);
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+/// The server IP and port of an `SSH_CONNECTION` value, `client_ip client_port server_ip server_port`
+fn ssh_server(ssh_connection: &str) -> Result<(IpAddr, u16)> {
+    let mut pieces = ssh_connection.split(' ').skip(2);
+    let ssh_server_ip = IpAddr::from_str(pieces.next().ok_or_else(||anyhow!("Missing server IP"))?)?;
+    let ssh_server_port = u16::from_str(pieces.next().ok_or_else(||anyhow!("Missing server port"))?)?;
+    Ok((ssh_server_ip, ssh_server_port))
+}
+
+/// A `whoami_format` template with `{user}`, `{host}`, `{shorthost}` (up to the first `.`), `{ip}` and `{port}` filled
+/// in, the last two being empty outside SSH sessions
+fn whoami_format(template: &str, user: &str, host: &str, server: Option<(IpAddr, u16)>) -> String {
+    let (ip, port) = server.map_or((String::new(), String::new()), |(ip, port)| (ip.to_string(), port.to_string()));
+    let short_host = host.split('.').next().unwrap_or(host);
+    template
+        .replace("{user}", &user.red().bold().to_string())
+        .replace("{host}", &host.red().bold().to_string())
+        .replace("{shorthost}", &short_host.red().bold().to_string())
+        .replace("{ip}", &ip)
+        .replace("{port}", &port)
+}
+
+/// The signal that killed a command exiting with `code`, e.g. `SIGINT` for 130, as shells report it as 128 plus the signal
+#[cfg(all(feature="platform", unix))]
+fn signal_name(code: i32) -> Option<&'static str> {
+    use std::convert::TryFrom;
+    if !(129..=192).contains(&code) {
+        return None;
+    }
+    nix::sys::signal::Signal::try_from(code - 128).ok().map(|signal| signal.as_str())
+}
+
+#[cfg(not(all(feature="platform", unix)))]
+fn signal_name(_code: i32) -> Option<&'static str> {
+    None
+}
+
+/// Established connections above which `connections` turns yellow, unless overridden by `connections_warn`
+#[cfg(all(feature="network", target_os="linux"))]
+const CONNECTIONS_WARN: usize = 20;
+
+/// Processes looked through for the session's sockets before `connections` gives up, so it stays bounded on busy hosts
+#[cfg(all(feature="network", target_os="linux"))]
+const CONNECTIONS_MAX_PROCESSES: usize = 4096;
+
+/// The session id in a `/proc/<pid>/stat` line
+#[cfg(all(feature="network", target_os="linux"))]
+fn stat_session(stat: &str) -> Option<u32> {
+    // The command name is parenthesized and can itself contain spaces and parentheses
+    stat.get(stat.rfind(')')? + 1..)?.split_whitespace().nth(3)?.parse().ok()
+}
+
+/// The inodes of the sockets open in this process's session, or `None` if there are too many processes to look through
+#[cfg(all(feature="network", target_os="linux"))]
+fn session_sockets() -> Option<std::collections::HashSet<u64>> {
+    let session = stat_session(&std::fs::read_to_string("/proc/self/stat").ok()?)?;
+    let mut inodes = std::collections::HashSet::new();
+    let processes = std::fs::read_dir("/proc").ok()?
+        .flatten()
+        .filter(|entry| entry.file_name().to_str().is_some_and(|name| name.bytes().all(|b| b.is_ascii_digit())));
+    for (i, process) in processes.enumerate() {
+        if i == CONNECTIONS_MAX_PROCESSES {
+            return None;
+        }
+        // Processes that exited since the listing or whose descriptors aren't readable are skipped
+        if std::fs::read_to_string(process.path().join("stat")).ok().as_deref().and_then(stat_session) != Some(session) {
+            continue;
         }
-        fw.print_section(Field::Prompt)?;
-        out_written.len()
+        for fd in std::fs::read_dir(process.path().join("fd")).into_iter().flatten().flatten() {
+            let target = std::fs::read_link(fd.path()).ok();
+            if let Some(inode) = target.as_deref().and_then(Path::to_str).and_then(|t| t.strip_prefix("socket:[")?.strip_suffix(']')?.parse().ok()) {
+                inodes.insert(inode);
+            }
+        }
+    }
+    Some(inodes)
+}
+
+/// The established connections in a `/proc/net/tcp` or `/proc/net/tcp6` table whose socket is one of `inodes`
+#[cfg(all(feature="network", target_os="linux"))]
+fn established_connections(table: &str, inodes: &std::collections::HashSet<u64>) -> usize {
+    table.lines().skip(1).filter(|line| {
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        // `01` is TCP_ESTABLISHED
+        columns.get(3) == Some(&"01") && columns.get(9).and_then(|inode| inode.parse().ok()).is_some_and(|inode| inodes.contains(&inode))
+    }).count()
+}
+
+/// `$XDG_RUNTIME_DIR/omniprompt/network`, or under the cache directory on platforms without a runtime directory
+/// (macOS, Windows), or under the temporary directory as a last resort
+#[cfg(feature="network")]
+fn network_state_file(runtime_dir: Option<PathBuf>, cache_dir: Option<PathBuf>) -> PathBuf {
+    runtime_dir.or(cache_dir).unwrap_or_else(std::env::temp_dir).join("omniprompt").join("network")
+}
+
+/// Upload and download bytes per second since the sample recorded in `path`, recording the `(interface, received,
+/// transmitted)` totals taken at `now` (in milliseconds) for the next prompt
+#[cfg(feature="network")]
+fn network_rates(path: &Path, now: i64, sample: &[(String, u64, u64)]) -> Result<Option<(u64, u64)>> {
+    let previous = std::fs::read_to_string(path).ok().and_then(|contents| config::parse_file(&contents).ok()).unwrap_or_default();
+    let mut state = format!("time = {}\n", now);
+    for (name, received, transmitted) in sample {
+        state.push_str(&format!("{}.received = {}\n{}.transmitted = {}\n", name, received, name, transmitted));
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, state)?;
+
+    let elapsed = match previous.get("time").and_then(|t| t.parse::<i64>().ok()) {
+        Some(time) if now > time => (now - time) as f64 / 1000.0,
+        _ => return Ok(None),
     };
-    std::io::stdout().write_all(&out[..out_len])?;
+    let counter = |name: &str, counter: &str| previous.get(&format!("{}.{}", name, counter)).and_then(|v| v.parse::<u64>().ok());
+    let (upload, download) = network_totals(sample.iter().filter_map(|(name, received, transmitted)| {
+        Some((received.saturating_sub(counter(name, "received")?), transmitted.saturating_sub(counter(name, "transmitted")?)))
+    }));
+    Ok(Some(((upload as f64 / elapsed) as u64, (download as f64 / elapsed) as u64)))
+}
+
+/// Write network traffic as `↑{up}↓{down}`, with the glyphs and the separator between them configurable through
+/// `net_up_glyph`, `net_down_glyph` and `net_sep`
+#[cfg(feature="network")]
+fn print_network<W: Write>(stream: &mut W, upload: impl std::fmt::Display, download: impl std::fmt::Display, config: &Config) -> Result<()> {
+    if colors::is_plain() {
+        write!(stream, "{} up, {} down", upload, download)?;
+    } else {
+        let glyph = |key| config.get(key).unwrap_or_default();
+        write!(stream, "{}{}{}{}{}", glyph("net_up_glyph"), upload, glyph("net_sep"), glyph("net_down_glyph"), download)?;
+    }
     Ok(())
 }
 
-fn main() -> Result<()> {
-    let rval = std::env::args_os().nth(1).filter(|s|!s.is_empty()).map(|s|i32::from_str(&s.to_string_lossy())).transpose()?;
-    print_default(rval)
+/// Tools beyond which `Field::Asdf` and `Field::Mise` only show how many more there are
+#[cfg(any(feature="asdf", feature="mise"))]
+const TOOL_VERSIONS_SHOWN: usize = 3;
+
+/// The tools of a `.tool-versions` file and each one's preferred version
+#[cfg(any(feature="asdf", feature="mise"))]
+fn parse_tool_versions(contents: &str) -> Vec<(String, String)> {
+    contents.lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            Some((words.next()?.to_string(), words.next()?.to_string()))
+        })
+        .collect()
 }
 
-// Not comprehensive, but sanity checking
-#[cfg(test)]
-mod test {
-    use super::*;
+/// Tools as `node@20 python@3.12`, counting those past `TOOL_VERSIONS_SHOWN`
+#[cfg(any(feature="asdf", feature="mise"))]
+fn tool_summary(tools: &[(String, String)]) -> String {
+    let mut summary = tools.iter().take(TOOL_VERSIONS_SHOWN).map(|(tool, version)| format!("{}@{}", tool, version)).collect::<Vec<_>>().join(" ");
+    if tools.len() > TOOL_VERSIONS_SHOWN {
+        summary.push_str(&format!(" +{}", tools.len() - TOOL_VERSIONS_SHOWN));
+    }
+    summary
+}
 
-    macro_rules! test {
-        ($name:ident, $field:expr) => {
-            #[test]
-            fn $name() {
-                {
-                    let stdout = std::io::stdout();
-                    setup(stdout.lock(), Some(0)).print_section($field).unwrap();
+/// A `.tool-versions` file as `node@20 python@3.12`, keeping only each tool's preferred version
+#[cfg(feature="asdf")]
+fn tool_versions(contents: &str) -> String {
+    tool_summary(&parse_tool_versions(contents))
+}
+
+/// The config files mise reads from a directory, highest precedence first
+#[cfg(feature="mise")]
+const MISE_CONFIGS: &[&str] = &["mise.local.toml", ".mise.local.toml", "mise.toml", ".mise.toml", ".mise/config.toml", ".config/mise.toml", ".config/mise/config.toml"];
+
+/// The tools pinned by the nearest directory at or above `dir` with a mise config file, merged with the first
+/// file to name a tool winning, or `None` outside any. A `.tool-versions` counts only if mise is `activated`, as
+/// otherwise it's as likely to be asdf's.
+#[cfg(feature="mise")]
+fn mise_tools(dir: &Path, activated: bool) -> Option<Vec<(String, String)>> {
+    for dir in dir.ancestors() {
+        let mut found = false;
+        let mut tools: Vec<(String, String)> = Vec::new();
+        for name in MISE_CONFIGS.iter().copied().chain(Some(".tool-versions").filter(|_| activated)) {
+            let contents = match std::fs::read_to_string(dir.join(name)) {
+                Ok(contents) => contents,
+                Err(_) => continue,
+            };
+            found = true;
+            let pinned = if name.ends_with(".toml") { mise_toml_tools(&contents) } else { parse_tool_versions(&contents) };
+            for (tool, version) in pinned {
+                if !tools.iter().any(|(t, _)| *t == tool) {
+                    tools.push((tool, version));
                 }
-                {
-                    let stdout = std::io::stdout();
-                    setup(stdout.lock(), Some(1)).print_section($field).unwrap();
+            }
+        }
+        if found {
+            return Some(tools);
+        }
+    }
+    None
+}
+
+/// The `[tools]` table of a mise config file, taking the first of several versions (`python = ["3.12", "3.11"]`)
+/// and the `version` of a tool's options (`node = { version = "20" }`)
+#[cfg(feature="mise")]
+fn mise_toml_tools(contents: &str) -> Vec<(String, String)> {
+    let mut tools = Vec::new();
+    let mut in_tools = false;
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')).filter(|s| !s.contains('"')) {
+            in_tools = section.trim() == "tools";
+            continue;
+        }
+        let (tool, value) = match line.split_once('=') {
+            Some((tool, value)) if in_tools => (tool.trim().trim_matches('"'), value.trim()),
+            _ => continue,
+        };
+        let version = if value.starts_with(['[', '{']) { value.split('"').nth(1) } else { Some(value.trim_matches(['"', '\''])) };
+        if let Some(version) = version.filter(|v| !v.is_empty()) {
+            tools.push((tool.to_string(), version.to_string()));
+        }
+    }
+    tools
+}
+
+/// The charge percentage of the first battery under `power_supply` (`/sys/class/power_supply`), and whether it is
+/// charging (`None` when neither charging nor discharging, e.g. full)
+#[cfg(all(feature="battery", target_os="linux"))]
+fn battery(power_supply: &Path) -> Option<(u8, Option<bool>)> {
+    let read = |supply: &Path, name: &str| std::fs::read_to_string(supply.join(name)).ok().map(|s| s.trim().to_string());
+    let mut supplies: Vec<PathBuf> = std::fs::read_dir(power_supply).ok()?.filter_map(|e| Some(e.ok()?.path())).collect();
+    supplies.sort();
+    let supply = supplies.into_iter().find(|supply| read(supply, "type").as_deref() == Some("Battery"))?;
+    let percent = read(&supply, "capacity")?.parse().ok()?;
+    let charging = match read(&supply, "status").as_deref() {
+        Some("Charging") => Some(true),
+        Some("Discharging") => Some(false),
+        _ => None,
+    };
+    Some((percent, charging))
+}
+
+/// Write a battery charge with an arrow for its direction, green above 50%, yellow above 20% and red below
+#[cfg(all(feature="battery", target_os="linux"))]
+fn print_battery<W: Write>(stream: &mut W, percent: u8, charging: Option<bool>) -> Result<()> {
+    let arrow = match charging {
+        Some(true) => "↑",
+        Some(false) => "↓",
+        None => "",
+    };
+    let text = format!("{}{}%", arrow, percent);
+    if percent > 50 {
+        write!(stream, "{}", text.green())?;
+    } else if percent >= 20 {
+        write!(stream, "{}", text.yellow())?;
+    } else {
+        write!(stream, "{}", text.red())?;
+    }
+    Ok(())
+}
+
+/// Write the 1, 5 and 15 minute load averages, each red above `cores`, yellow above half of it and green otherwise
+#[cfg(all(feature="load", unix))]
+fn print_load<W: Write>(stream: &mut W, load: [f64; 3], cores: usize) -> Result<()> {
+    for (i, value) in load.iter().enumerate() {
+        if i > 0 {
+            write!(stream, " ")?;
+        }
+        let text = format!("{:.2}", value);
+        if *value > cores as f64 {
+            write!(stream, "{}", text.red())?;
+        } else if *value > cores as f64 / 2.0 {
+            write!(stream, "{}", text.yellow())?;
+        } else {
+            write!(stream, "{}", text.green())?;
+        }
+    }
+    Ok(())
+}
+
+/// How much of the memory usage is shown, from `mem_display`
+#[cfg(feature="memory")]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+enum MemDisplay {
+    /// `8.1GB/16.0GB`
+    #[default]
+    UsedTotal,
+    /// `8.1GB`
+    Used,
+    /// `51%`
+    Percent,
+}
+
+#[cfg(feature="memory")]
+fn mem_display(config: &Config) -> Result<MemDisplay> {
+    match config.get("mem_display") {
+        None | Some("used_total") => Ok(MemDisplay::UsedTotal),
+        Some("used") => Ok(MemDisplay::Used),
+        Some("percent") => Ok(MemDisplay::Percent),
+        Some(other) => Err(anyhow!("parsing mem_display {:?}: expected percent, used or used_total", other)),
+    }
+}
+
+/// Write memory usage as `8.1GB/16.0GB` or as much of it as `display` asks for, yellow above 75% and red above 90%
+#[cfg(feature="memory")]
+fn print_memory<W: Write>(stream: &mut W, used: u64, total: u64, display: MemDisplay) -> Result<()> {
+    use bytesize::ByteSize;
+    if total == 0 {
+        return Ok(());
+    }
+    let size = |bytes| ByteSize(bytes).to_string().replace(' ', "");
+    let percent = used as f64 * 100.0 / total as f64;
+    let text = match display {
+        MemDisplay::UsedTotal => format!("{}/{}", size(used), size(total)),
+        MemDisplay::Used => size(used),
+        MemDisplay::Percent => format!("{:.0}%", percent),
+    };
+    if percent > 90.0 {
+        write!(stream, "{}", text.red())?;
+    } else if percent > 75.0 {
+        write!(stream, "{}", text.yellow())?;
+    } else {
+        write!(stream, "{}", text)?;
+    }
+    Ok(())
+}
+
+/// `$XDG_CACHE_HOME/omniprompt/bandwidth`
+#[cfg(feature="network")]
+fn bandwidth_cache() -> Option<PathBuf> {
+    dirs::cache_dir().map(|d| d.join("omniprompt").join("bandwidth"))
+}
+
+/// Write the `download = 50` and `upload = 10` Mbps of a speedtest cached by a background job; the prompt never
+/// measures bandwidth itself
+#[cfg(feature="network")]
+fn print_bandwidth<W: Write>(stream: &mut W, cache: &config::Layer) -> Result<()> {
+    let parse = |key: &str| cache.get(key).map(|v| v.parse::<f64>().map_err(|e| anyhow!("parsing {} {:?}: {}", key, v, e))).transpose();
+    let mut first = true;
+    for (key, icon, word) in [("download", "↓", "down"), ("upload", "↑", "up")] {
+        if let Some(mbps) = parse(key)? {
+            if !first {
+                write!(stream, " ")?;
+            }
+            if colors::is_plain() {
+                write!(stream, "{:.0}Mbps {}", mbps, word)?;
+            } else {
+                write!(stream, "{}", format_args!("{}{:.0}Mbps", icon, mbps).cyan())?;
+            }
+            first = false;
+        }
+    }
+    Ok(())
+}
+
+/// Whether any of `mailboxes` (bash's `MAILPATH` `?message` suffixes are ignored) was modified since it was last
+/// read, the classic "you have mail" check
+#[cfg(unix)]
+fn has_new_mail(mailboxes: &[PathBuf]) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    mailboxes.iter().any(|mailbox| {
+        let mailbox = mailbox.to_string_lossy();
+        let path = mailbox.split_once('?').map_or(&*mailbox, |(path, _)| path);
+        match std::fs::metadata(path) {
+            Ok(metadata) => metadata.len() > 0 && (metadata.mtime(), metadata.mtime_nsec()) > (metadata.atime(), metadata.atime_nsec()),
+            Err(_) => false,
+        }
+    })
+}
+
+/// The compact MAC context from the first readable of `attrs`: the type of an SELinux context
+/// (`user_u:role_r:type_t:s0`) or the name of an AppArmor profile (`name (enforce)`), unless unconfined
+#[cfg(all(feature="security", target_os="linux"))]
+fn security_context(attrs: &[&Path]) -> Option<String> {
+    let context = attrs.iter().find_map(|attr| std::fs::read_to_string(attr).ok())?;
+    let context = context.trim_end_matches(['\0', '\n']).trim();
+    let compact = match context.split(':').collect::<Vec<_>>().as_slice() {
+        [_, _, kind, ..] => kind.to_string(),
+        _ => context.split(" (").next().unwrap_or_default().to_string(),
+    };
+    Some(compact).filter(|c| !c.is_empty() && c != "unconfined" && c != "kernel")
+}
+
+/// Write whether `stdin` is interactive, as `tty` or `pipe`
+fn print_stdin<W: Write>(stream: &mut W, stdin: impl std::io::IsTerminal) -> Result<()> {
+    write!(stream, "{}", (if stdin.is_terminal() { "tty" } else { "pipe" }).dim())?;
+    Ok(())
+}
+
+/// The topmost applied patch of the quilt series (`patches/series`, applied per `.pc/applied-patches`) or StGit stack
+/// (`.git/patches/<branch>/applied`) in `dir` or the nearest ancestor that has one
+#[cfg(feature="patch")]
+fn top_patch(dir: &Path) -> Option<String> {
+    let last_line = |path: PathBuf| std::fs::read_to_string(path).ok()?.lines().rev().map(str::trim).find(|l| !l.is_empty()).map(str::to_string);
+    for dir in dir.ancestors() {
+        if dir.join("patches").join("series").is_file() {
+            return last_line(dir.join(".pc").join("applied-patches"));
+        }
+        let git_dir = dir.join(".git");
+        if git_dir.is_dir() {
+            let head = std::fs::read_to_string(git_dir.join("HEAD")).ok()?;
+            let branch = head.trim().strip_prefix("ref: refs/heads/")?;
+            return last_line(git_dir.join("patches").join(branch).join("applied"));
+        }
+    }
+    None
+}
+
+/// The WSL distribution from `$WSL_DISTRO_NAME`, or just `WSL` when only `/proc/version` gives it away
+fn wsl_distro(distro_name: Option<String>, proc_version: Option<String>) -> Option<String> {
+    match distro_name.filter(|d| !d.is_empty()) {
+        Some(distro) => Some(distro),
+        None => proc_version.filter(|v| v.to_ascii_lowercase().contains("microsoft")).map(|_| "WSL".to_string()),
+    }
+}
+
+/// The Flatpak or Snap sandbox the prompt runs in, as `flatpak org.gnome.Terminal` or `snap alacritty`, from
+/// `$FLATPAK_ID` or the `[Application]` name in `/.flatpak-info`, and `$SNAP_NAME` or `$SNAP` (`/snap/<name>/<rev>`)
+fn sandbox_app(config: &Config, flatpak_info: Option<&str>) -> Option<String> {
+    let flatpak_id = config.env("FLATPAK_ID").filter(|id| !id.is_empty()).map(str::to_string).or_else(|| {
+        let mut in_application = false;
+        flatpak_info?.lines().map(str::trim).find_map(|line| {
+            if line.starts_with('[') {
+                in_application = line == "[Application]";
+                None
+            } else if in_application {
+                line.strip_prefix("name=").map(str::to_string)
+            } else {
+                None
+            }
+        })
+    });
+    if let Some(id) = flatpak_id {
+        return Some(format!("flatpak {}", id));
+    }
+    let snap = config.env("SNAP_NAME").filter(|name| !name.is_empty())
+        .or_else(|| config.env("SNAP")?.strip_prefix("/snap/")?.split('/').next().filter(|name| !name.is_empty()))?;
+    Some(format!("snap {}", snap))
+}
+
+/// The current context of a kubeconfig and the host of its cluster's API server, from the block-style YAML that
+/// kubectl writes
+#[cfg(feature="kube")]
+fn kube_context(kubeconfig: &str) -> Option<(String, Option<String>)> {
+    // Each item of a top-level list as its `key: value` pairs, ignoring nesting
+    let items = |list: &str| {
+        let mut items: Vec<Vec<(&str, &str)>> = Vec::new();
+        let mut in_list = false;
+        for line in kubeconfig.lines() {
+            if !line.starts_with(' ') && !line.starts_with('-') {
+                in_list = line.trim_end() == format!("{}:", list);
+                continue;
+            }
+            if !in_list {
+                continue;
+            }
+            let line = line.trim();
+            let line = match line.strip_prefix("- ") {
+                Some(rest) => {
+                    items.push(Vec::new());
+                    rest
                 }
+                None => line,
+            };
+            if let (Some(item), Some((key, value))) = (items.last_mut(), line.split_once(':')) {
+                item.push((key.trim(), value.trim().trim_matches('"')));
             }
         }
+        items
+    };
+    let named = |list: &str, name: &str, key: &str| items(list).into_iter()
+        .find(|item| item.contains(&("name", name)))
+        .and_then(|item| item.iter().find(|(k, v)| *k == key && !v.is_empty()).map(|(_, v)| v.to_string()));
+    let context = kubeconfig.lines().find_map(|line| line.strip_prefix("current-context:"))?.trim().trim_matches('"');
+    if context.is_empty() {
+        return None;
     }
+    let server = named("contexts", context, "cluster").and_then(|cluster| named("clusters", &cluster, "server")).map(|server| {
+        let host = server.split_once("://").map_or(server.as_str(), |(_, rest)| rest);
+        host.split([':', '/']).next().unwrap_or_default().to_string()
+    });
+    Some((context.to_string(), server))
+}
 
-    fn setup<T: Write>(stream: T, rval: Option<i32>) -> FieldWriter<T> {
-        FieldWriter::new(stream, rval.into())
+/// The innermost systemd scope or service (e.g. `session-2.scope`) in a `/proc/self/cgroup` listing, from the unified
+/// hierarchy or else the `name=systemd` one
+#[cfg(all(feature="systemd", target_os="linux"))]
+fn systemd_unit(cgroup: &str) -> Option<&str> {
+    let path = |hierarchy: &str| cgroup.lines().find_map(|line| {
+        let mut parts = line.splitn(3, ':');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(_), Some(h), Some(path)) if h == hierarchy => Some(path),
+            _ => None,
+        }
+    });
+    path("").or_else(|| path("name=systemd"))?
+        .rsplit('/')
+        .find(|unit| unit.ends_with(".scope") || unit.ends_with(".service"))
+}
+
+/// The name of group `gid`, or the number itself when it has none
+#[cfg(all(feature="platform", unix))]
+fn group_name(gid: nix::unistd::Gid) -> String {
+    match nix::unistd::Group::from_gid(gid) {
+        Ok(Some(group)) => group.name,
+        _ => gid.to_string(),
     }
+}
 
-    test!(exit_code, Field::ExitCode);
-    #[cfg(feature="git")]
-    test!(git, Field::Git);
-    #[cfg(feature="network")]
-    test!(network, Field::Network);
-    #[cfg(feature="platform")]
-    test!(platform, Field::Platform);
-    test!(ppid, Field::Ppid);
-    test!(prompt, Field::Prompt);
-    test!(pwd, Field::Pwd);
-    test!(time, Field::Time);
-    #[cfg(feature="tty")]
-    test!(tty, Field::Tty);
-    test!(whoami, Field::Whoami);
+/// Filesystem types served over the network, where commands may stall on the server
+#[cfg(target_os="linux")]
+const NETWORK_FILESYSTEMS: &[&str] = &["9p", "afs", "ceph", "cifs", "fuse.sshfs", "glusterfs", "ncpfs", "nfs", "nfs4", "smb3", "smbfs", "sshfs"];
 
-    #[test]
-    fn default() {
-        print_default(Some(0)).unwrap();
-        print_default(Some(1)).unwrap();
+/// The type of the network filesystem `dir` is on according to a `/proc/mounts` listing, if it's on one
+#[cfg(target_os="linux")]
+fn network_mount<'a>(mounts: &'a str, dir: &Path) -> Option<&'a str> {
+    // Mount points escape spaces and the like as octal, e.g. `\040`
+    let unescape = |path: &str| {
+        let mut unescaped = Vec::new();
+        let mut bytes = path.bytes();
+        while let Some(b) = bytes.next() {
+            if b == b'\\' {
+                let digits: Vec<u8> = bytes.by_ref().take(3).collect();
+                unescaped.push(std::str::from_utf8(&digits).ok().and_then(|d| u8::from_str_radix(d, 8).ok()).unwrap_or(b'?'));
+            } else {
+                unescaped.push(b);
+            }
+        }
+        PathBuf::from(String::from_utf8_lossy(&unescaped).into_owned())
+    };
+    // The last of the deepest mounts containing `dir` is the one on top
+    let (_, fs_type) = mounts.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().skip(1);
+            Some((unescape(fields.next()?), fields.next()?))
+        })
+        .filter(|(mount_point, _)| dir.starts_with(mount_point))
+        .enumerate()
+        .max_by_key(|(i, (mount_point, _))| (mount_point.components().count(), *i))?
+        .1;
+    Some(fs_type).filter(|t| NETWORK_FILESYSTEMS.contains(t))
+}
+
+/// Write a red `ro` when `flags` are those of a filesystem mounted read-only
+#[cfg(all(feature="platform", unix))]
+fn print_read_only<W: Write>(stream: &mut W, flags: nix::sys::statvfs::FsFlags) -> Result<()> {
+    if flags.contains(nix::sys::statvfs::FsFlags::ST_RDONLY) {
+        write!(stream, "{}", "ro".red())?;
+    }
+    Ok(())
+}
+
+/// `s` without the arrows, shapes and other symbols used as icons, which screen readers announce unhelpfully
+fn strip_icons(s: &str) -> String {
+    s.chars()
+        .filter(|c| !matches!(c, '\u{2190}'..='\u{21ff}' | '\u{2300}'..='\u{23ff}' | '\u{25a0}'..='\u{27bf}' | '\u{27f0}'..='\u{27ff}'))
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Write a temperature, yellow once warm and red once hot
+#[cfg(feature="platform")]
+fn print_temperature<W: Write>(stream: &mut W, celsius: f32) -> Result<()> {
+    let text = format!("{:.0}°C", celsius);
+    if celsius >= 85.0 {
+        write!(stream, "{}", text.red().bold())?;
+    } else if celsius >= 70.0 {
+        write!(stream, "{}", text.yellow())?;
+    } else {
+        write!(stream, "{}", text.green())?;
+    }
+    Ok(())
+}
+
+/// How durations are rounded, from `duration_format`
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+enum DurationFormat {
+    /// The largest units only: `45s`, `12m`, `3h12m`
+    #[default]
+    Coarse,
+    /// `250ms` below a second, `1.2s` below a minute and `2m 5s` above
+    Auto,
+    /// Whole seconds: `1s`
+    Seconds,
+    /// Whole milliseconds: `1234ms`
+    Millis,
+}
+
+fn duration_format(config: &Config) -> Result<DurationFormat> {
+    match config.get("duration_format") {
+        None | Some("coarse") => Ok(DurationFormat::Coarse),
+        Some("auto") => Ok(DurationFormat::Auto),
+        Some("s") => Ok(DurationFormat::Seconds),
+        Some("ms") => Ok(DurationFormat::Millis),
+        Some(other) => Err(anyhow!("parsing duration_format {:?}: expected coarse, auto, s or ms", other)),
+    }
+}
+
+fn format_duration(duration: Duration, format: DurationFormat) -> String {
+    let secs = duration.as_secs();
+    match format {
+        DurationFormat::Coarse => match (secs / 3600, secs / 60 % 60) {
+            (0, 0) => format!("{}s", secs),
+            (0, minutes) => format!("{}m", minutes),
+            (hours, minutes) => format!("{}h{}m", hours, minutes),
+        },
+        DurationFormat::Auto if secs == 0 => format!("{}ms", duration.as_millis()),
+        DurationFormat::Auto if secs < 60 => format!("{:.1}s", duration.as_secs_f64()),
+        DurationFormat::Auto => format!("{}m {}s", secs / 60, secs % 60),
+        DurationFormat::Seconds => format!("{}s", duration.as_secs_f64().round()),
+        DurationFormat::Millis => format!("{}ms", duration.as_millis()),
+    }
+}
+
+/// Right-pad `contents` with spaces to `width` visible columns, so whatever follows starts at the same column as the
+/// contents vary in length
+fn pad(contents: &mut Vec<u8>, width: usize) {
+    let visible = colors::width(&String::from_utf8_lossy(contents));
+    contents.resize(contents.len() + width.saturating_sub(visible), b' ');
+}
+
+/// Bytes of a `file_show` file read looking for its first line
+const FILE_SHOW_READ: u64 = 4096;
+
+/// Characters of a `file_show` line shown before it's cut off with `…`
+const FILE_SHOW_WIDTH: usize = 40;
+
+/// The trimmed first line of the file at `path`, or `None` if it's missing or blank
+fn first_line(path: &Path) -> Option<String> {
+    use std::io::Read;
+    let mut contents = Vec::new();
+    std::fs::File::open(path).ok()?.take(FILE_SHOW_READ).read_to_end(&mut contents).ok()?;
+    let contents = String::from_utf8_lossy(&contents);
+    let line = contents.lines().next()?.trim();
+    match line.char_indices().nth(FILE_SHOW_WIDTH) {
+        _ if line.is_empty() => None,
+        Some((end, _)) => Some(format!("{}…", &line[..end])),
+        None => Some(line.to_string()),
+    }
+}
+
+/// `$XDG_CACHE_HOME/omniprompt/last_prompt`
+fn last_prompt_file() -> Option<PathBuf> {
+    dirs::cache_dir().map(|d| d.join("omniprompt").join("last_prompt"))
+}
+
+/// Seconds since the epoch `now` was last recorded in `path`, recording `now` for the next prompt
+fn since_last_prompt(path: &Path, now: i64) -> Result<Option<u64>> {
+    let previous = std::fs::read_to_string(path).ok().and_then(|s| s.trim().parse::<i64>().ok());
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, now.to_string())?;
+    Ok(previous.map(|previous| now.saturating_sub(previous).max(0) as u64))
+}
+
+/// `path` with every component but the last shortened to its first character (keeping a leading `.`), fish-style:
+/// `/usr/local/bin/foo` becomes `/u/l/b/foo`
+fn abbreviate(path: &str) -> String {
+    let components: Vec<&str> = path.split('/').collect();
+    let last = components.len() - 1;
+    components.iter().enumerate().map(|(i, component)| {
+        if i == last {
+            return component.to_string();
+        }
+        let prefix = if component.starts_with('.') { 2 } else { 1 };
+        component.chars().take(prefix).collect()
+    }).collect::<Vec<String>>().join("/")
+}
+
+/// Gradient endpoints for `pwd_style = gradient`, from the first path component to the last
+const GRADIENT: ((u8, u8, u8), (u8, u8, u8)) = ((255, 215, 0), (255, 0, 175));
+
+/// `path` with each component in a truecolor step of `GRADIENT`
+fn gradient(path: &str) -> String {
+    let segments: Vec<&str> = path.split_inclusive('/').collect();
+    let ((r0, g0, b0), (r1, g1, b1)) = GRADIENT;
+    let lerp = |from: u8, to: u8, t: f32| (from as f32 + (to as f32 - from as f32) * t).round() as u8;
+    segments.iter().enumerate().map(|(i, segment)| {
+        let t = if segments.len() > 1 { i as f32 / (segments.len() - 1) as f32 } else { 0.0 };
+        colors::Rgb((lerp(r0, r1, t), lerp(g0, g1, t), lerp(b0, b1, t)), segment).to_string()
+    }).collect()
+}
+
+/// The shell's `$PWD` when it reached `cwd` through a symlink, i.e. when it's a different path resolving to `cwd`
+fn logical_pwd(pwd: Option<&str>, cwd: &Path) -> Option<PathBuf> {
+    let pwd = Path::new(pwd?);
+    // A `$PWD` left stale by a parent that changed directory doesn't resolve to `cwd`
+    (pwd != cwd && pwd.canonicalize().ok()? == cwd.canonicalize().ok()?).then(|| pwd.to_path_buf())
+}
+
+/// `path` with the home directory abbreviated to `~`
+fn home_relative(path: &Path, home_dir: Option<&Path>) -> PathBuf {
+    match home_dir.map(|home_dir| path.strip_prefix(home_dir)) {
+        Some(Ok(relpath)) if !relpath.as_os_str().is_empty() => Path::new("~").join(relpath),
+        Some(Ok(_)) => "~".into(),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Components `pwd_style = truncate` keeps when `pwd_max_components` isn't set
+const PWD_TRUNCATE_COMPONENTS: usize = 3;
+
+/// How `Field::Pwd` displays `cwd`: relative to the repository root as `repo:sub/dir` when `pwd_repo` is enabled and
+/// `cwd` is inside a git working tree, otherwise relative to the home directory, cut down to `pwd_max_components`,
+/// and shaped by `pwd_style`: `full` (or `gradient`, which only colors it), `abbreviate` (or `abbrev`) or `truncate`
+fn pwd(cwd: &Path, home_dir: Option<&Path>, config: &Config) -> Result<String> {
+    let pwd = relative_pwd(cwd, home_dir, config);
+    let max = config.parse::<usize>("pwd_max_components")?;
+    let pwd = match max {
+        Some(max) => truncate_components(&pwd, max),
+        None => pwd,
+    };
+    match config.get("pwd_style") {
+        None | Some("" | "full" | "gradient") => Ok(pwd),
+        Some("abbreviate" | "abbrev") => Ok(abbreviate(&pwd)),
+        Some("truncate") => Ok(truncate_components(&pwd, max.unwrap_or(PWD_TRUNCATE_COMPONENTS))),
+        Some(other) => Err(anyhow!("parsing pwd_style {:?}: expected full, abbreviate, truncate or gradient", other)),
+    }
+}
+
+/// `path` with its middle components collapsed into `…` when it has more than `max` below `/` or `~`, keeping the
+/// first and as many of the last as fit, and always the last one: `~/src/omniprompt/src/bin` becomes
+/// `~/src/…/src/bin` for a `max` of 3
+fn truncate_components(path: &str, max: usize) -> String {
+    let (root, rest) = match (path.strip_prefix('/'), path.strip_prefix("~/")) {
+        (Some(rest), _) => ("/", rest),
+        (None, Some(rest)) => ("~/", rest),
+        (None, None) => ("", path),
+    };
+    let components: Vec<&str> = rest.split('/').collect();
+    if components.len() <= max.max(1) {
+        return path.to_string();
+    }
+    let last = components[components.len() - max.saturating_sub(1).max(1)..].join("/");
+    if max < 2 {
+        return format!("…/{}", last);
+    }
+    format!("{}{}/…/{}", root, components[0], last)
+}
+
+fn relative_pwd(cwd: &Path, home_dir: Option<&Path>, config: &Config) -> String {
+    #[cfg(feature="git")]
+    if config.is_enabled("pwd_repo") {
+        if let Some(root) = git::workdir(cwd) {
+            if let (Some(name), Ok(relpath)) = (root.file_name(), cwd.strip_prefix(&root)) {
+                return format!("{}:{}", name.to_string_lossy(), relpath.display());
+            }
+        }
+    }
+    #[cfg(not(feature="git"))]
+    let _ = config;
+    home_relative(cwd, home_dir).display().to_string()
+}
+
+/// A sink that keeps everything on one line by writing `separator` in place of each newline, e.g. for logging
+struct SingleLine<'a, W: Write> {
+    inner: W,
+    separator: &'a str,
+}
+
+impl<W: Write> Write for SingleLine<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for (i, line) in buf.split(|&b| b == b'\n').enumerate() {
+            if i != 0 {
+                self.inner.write_all(self.separator.as_bytes())?;
+            }
+            self.inner.write_all(line)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Apply the `accessible`, `color` (`always`, `never` or `auto`), `no_color`, `shell` and `plain` options to the color layer
+fn configure_colors(config: &Config) -> Result<()> {
+    colors::set_plain(config.is_enabled("accessible"));
+    // For when the shell isn't the parent process, e.g. behind a wrapper script
+    if let Some(shell) = config.get("shell").filter(|s| !s.is_empty()) {
+        colors::set_escapes(colors::shell_escapes(shell).ok_or_else(|| anyhow!("parsing shell {:?}: expected zsh, bash, fish or none", shell))?);
+    }
+    // An explicit `color` wins over `no_color`, which wins over the conventional variables, and `FORCE_COLOR` over
+    // `NO_COLOR` (no-color.org). `FORCE_COLOR=0` or `false` is how tools spell leaving it unset.
+    let is_set = |name| config.env(name).is_some_and(|v| !v.is_empty());
+    let forced = config.env("FORCE_COLOR").is_some_and(|v| !v.is_empty() && v != "0" && v != "false");
+    colors::set_choice(match config.get("color") {
+        None if config.is_enabled("no_color") => colors::Choice::Never,
+        None if forced => colors::Choice::Always,
+        None if is_set("NO_COLOR") => colors::Choice::Never,
+        None | Some("always") => colors::Choice::Always,
+        Some("never") => colors::Choice::Never,
+        Some("auto") => colors::Choice::Auto,
+        Some(other) => return Err(anyhow!("parsing color {:?}: expected always, never or auto", other)),
+    });
+    // Text only, for logs and comparing prompts: no colors and nothing for the shell to skip over. JSON carries each
+    // field's color as a hint next to its text instead.
+    if config.is_enabled("plain") || is_json(config) {
+        colors::set_choice(colors::Choice::Never);
+        colors::set_escapes(("", ""));
+    }
+    Ok(())
+}
+
+/// The parent of this process according to sysinfo, for where `std` has no way to ask
+#[cfg(any(feature="sysinfo", not(unix)))]
+fn parent_pid() -> Option<sysinfo::Pid> {
+    let pid = sysinfo::get_current_pid().ok()?;
+    let mut system = sysinfo::System::new();
+    system.refresh_processes_specifics(sysinfo::ProcessesToUpdate::Some(&[pid]), sysinfo::ProcessRefreshKind::new());
+    system.process(pid)?.parent()
+}
+
+/// Moves the cursor to the first column of the last row (terminals clamp the row), so the rows printed after it
+/// scroll up and the input line stays pinned to the bottom of the terminal. Only for terminals that honor CUP.
+const ANCHOR_BOTTOM: &str = "\x1b[9999;1H";
+
+/// The escapes that mark where the prompt starts and where the command typed after it starts, from `prompt_markers`:
+/// OSC 133's semantic prompt marks, which many terminals use to jump between prompts, or Fig's OSC 697 ones
+fn prompt_markers(config: &Config) -> Result<Option<(&'static str, &'static str)>> {
+    match config.get("prompt_markers") {
+        None | Some("") => Ok(None),
+        Some("osc133") => Ok(Some(("\x1b]133;A\x07", "\x1b]133;B\x07"))),
+        Some("fig") => Ok(Some(("\x1b]697;NewCmd\x07\x1b]697;StartPrompt\x07", "\x1b]697;EndPrompt\x07"))),
+        Some(other) => Err(anyhow!("parsing prompt_markers {:?}: expected osc133 or fig", other)),
+    }
+}
+
+/// Render the default layout to `stdout`, reporting its size to `stderr` when `debug_len` is enabled
+fn print_default<O: Write, E: Write>(exit_code: Option<i32>, config: Config, stdout: &mut O, stderr: &mut E) -> Result<()> {
+    match config.get("format") {
+        None | Some("prompt" | "iterm-statusbar" | "json") => {}
+        Some(other) => return Err(anyhow!("parsing format {:?}: expected prompt, iterm-statusbar or json", other)),
+    }
+    // A `fields` list replaces the default layout, with `|` between rows and `,` between the fields of a row, and the
+    // fields after a row's `>` pushed to its right edge
+    let field_list = |list: &str| list.split(',').map(str::trim).filter(|n| !n.is_empty()).map(Field::from_name).collect::<Result<Vec<_>>>();
+    let fields = config.get("fields")
+        .map(|list| list.split('|').map(|row| {
+            let (left, right) = row.split_once('>').unwrap_or((row, ""));
+            Ok((field_list(left)?, field_list(right)?))
+        }).collect::<Result<Vec<_>>>())
+        .transpose()
+        .context("parsing fields")?;
+    configure_colors(&config)?;
+    let plain = config.is_enabled("plain");
+    // Nothing but the array goes into JSON, which has no rows to join either
+    let json = is_json(&config);
+    // A status bar or JSON has no prompt to delimit
+    let markers = prompt_markers(&config)?.filter(|_| config.get("format").is_none_or(|f| f == "prompt") && !json && !plain);
+    let debug_len = config.is_enabled("debug_len");
+    let single_line = config.is_enabled("single_line") && !json;
+    let separator = config.get("single_line_separator").unwrap_or_default().to_string();
+    // Like process-spawning fields, the filter is off limits in `safe` mode
+    let filter = config.get("filter").filter(|f| !f.is_empty() && !config.is_enabled("safe")).map(str::to_string);
+    let filter_timeout = Duration::from_millis(config.parse::<u64>("filter_timeout_ms")?.unwrap_or(200));
+    let deadline = config.parse::<u64>("deadline_ms")?.map(|ms| std::time::Instant::now() + Duration::from_millis(ms));
+    // Errors can carry backtraces, so the buffer has to be able to grow
+    let mut out = Vec::with_capacity(2048);
+    {
+        // let stdout = std::io::stdout();
+        // let mut fw = FieldWriter::new(stdout.lock(), exit_code, config);
+        let mut fw = FieldWriter::new(&mut out, exit_code, config);
+        fw.deadline = deadline;
+        if fw.config.is_enabled("anchor_bottom") && !plain && !json {
+            fw.write_all(colors::non_printing(ANCHOR_BOTTOM).as_bytes())?;
+        }
+        if let Some((start, _)) = markers {
+            fw.write_all(colors::non_printing(start).as_bytes())?;
+        }
+        if let Some(banner) = fw.config.get("banner").filter(|b| !b.is_empty() && !json).map(str::to_string) {
+            if fw.config.is_enabled("banner_color") {
+                writeln!(fw, "{}", banner.red().bold())?;
+            } else {
+                writeln!(fw, "{}", banner)?;
+            }
+        }
+
+        match &fields {
+            Some(rows) => {
+                for (i, (left, right)) in rows.iter().enumerate() {
+                    if i > 0 {
+                        fw.print_line()?;
+                    }
+                    for field in left.iter().filter(|f| **f != Field::Prompt) {
+                        fw.print_section(*field)?;
+                    }
+                    let right: Vec<Field> = right.iter().copied().filter(|f| *f != Field::Prompt).collect();
+                    fw.print_right_sections(&right)?;
+                }
+            }
+            None => {
+                fw.print_section(Field::Whoami)?;
+                #[cfg(all(feature="platform", unix))]
+                if fw.config.is_enabled("groups") {
+                    fw.print_section(Field::Groups)?;
+                }
+                fw.print_section(Field::Wsl)?;
+                fw.print_section(Field::Sandbox)?;
+                fw.print_section(Field::Pwd)?;
+                #[cfg(all(feature="platform", unix))]
+                fw.print_section(Field::ReadOnly)?;
+                #[cfg(target_os="linux")]
+                fw.print_section(Field::Mount)?;
+                fw.print_section(Field::OldPwd)?;
+                fw.print_section(Field::Ppid)?;
+                fw.print_section(Field::Time)?;
+                fw.print_section(Field::SessionTime)?;
+                #[cfg(all(feature="security", target_os="linux"))]
+                fw.print_section(Field::SecurityContext)?;
+                #[cfg(all(feature="systemd", target_os="linux"))]
+                fw.print_section(Field::Systemd)?;
+                // Most shells never touch a cluster, so reading the kubeconfig is left to those that ask
+                #[cfg(feature="kube")]
+                if fw.config.is_enabled("kube") {
+                    fw.print_section(Field::Kube)?;
+                }
+                #[cfg(feature="platform")]
+                fw.print_section(Field::Platform)?;
+                #[cfg(feature="platform")]
+                fw.print_section(Field::Temp)?;
+                #[cfg(feature="network")]
+                fw.print_section(Field::Network)?;
+                #[cfg(feature="network")]
+                fw.print_section(Field::Bandwidth)?;
+                // Walking every process's file descriptors is too slow to do unasked
+                #[cfg(all(feature="network", target_os="linux"))]
+                if fw.config.is_enabled("connections") {
+                    fw.print_section(Field::Connections)?;
+                }
+                #[cfg(feature="load")]
+                fw.print_section(Field::Load)?;
+                #[cfg(feature="memory")]
+                fw.print_section(Field::Memory)?;
+                #[cfg(all(feature="battery", target_os="linux"))]
+                fw.print_section(Field::Battery)?;
+                fw.print_line()?;
+                fw.print_section(Field::ExitCode)?;
+                fw.print_section(Field::Duration)?;
+                fw.print_section(Field::HistNum)?;
+                #[cfg(feature="patch")]
+                fw.print_section(Field::Patch)?;
+                #[cfg(feature="asdf")]
+                fw.print_section(Field::Asdf)?;
+                #[cfg(feature="mise")]
+                fw.print_section(Field::Mise)?;
+                fw.print_section(Field::File)?;
+                if fw.config.is_enabled("stdin") {
+                    fw.print_section(Field::Stdin)?;
+                }
+                #[cfg(unix)]
+                fw.print_section(Field::Mail)?;
+                #[cfg(feature="git")]
+                fw.print_section(Field::Git)?;
+                #[cfg(feature="git")]
+                fw.print_section(Field::GitConfig)?;
+                #[cfg(feature="git")]
+                fw.print_section(Field::GitAlias)?;
+                // Walking history is too slow to do unasked
+                #[cfg(feature="git")]
+                if fw.config.is_enabled("git_behind") {
+                    fw.print_section(Field::GitBehind)?;
+                }
+                #[cfg(feature="git")]
+                if fw.config.is_enabled("git_count") {
+                    fw.print_section(Field::GitCount)?;
+                }
+                #[cfg(feature="git")]
+                if fw.config.is_enabled("git_owner") {
+                    fw.print_section(Field::GitOwner)?;
+                }
+                #[cfg(feature="git")]
+                if fw.config.is_enabled("git_prs") {
+                    fw.print_section(Field::GitPrs)?;
+                }
+                #[cfg(feature="git")]
+                if fw.config.is_enabled("git_reachable") {
+                    fw.print_section(Field::GitRemote)?;
+                }
+                // Verifying runs gpg or ssh-keygen
+                #[cfg(feature="git")]
+                if fw.config.is_enabled("git_signature") {
+                    fw.print_section(Field::GitSignature)?;
+                }
+            }
+        }
+        if fw.has_errors() {
+            fw.print_line()?;
+            fw.print_errors()?;
+            fw.print_line()?;
+        }
+        // The error block already makes the prompt tall, so optionally drop the frame below it
+        if fw.has_errors() && fw.config.is_enabled("compact_errors") {
+            write!(fw, "{} ", "$".magenta().bold())?;
+        } else if fields.as_ref().is_none_or(|rows| rows.iter().any(|(left, right)| left.iter().chain(right).any(|f| *f == Field::Prompt))) {
+            // The input line always comes last, after any errors
+            fw.print_section(Field::Prompt)?;
+        }
+        // Padding the input row would push the cursor onto the next line
+        fw.close_band(false)?;
+        if let Some((_, end)) = markers {
+            fw.write_all(colors::non_printing(end).as_bytes())?;
+        }
+        if json {
+            fw.print_json()?;
+        } else if fw.config.is_enabled("field_offsets") && !plain && !single_line && filter.is_none() {
+            // Left out when `single_line` or the `filter` rewrite the prompt, as the offsets would no longer hold
+            fw.print_offsets()?;
+        }
+    }
+    if let Some(filter) = &filter {
+        match run_filter(filter, &out, filter_timeout) {
+            Ok(filtered) => out = filtered,
+            Err(e) => writeln!(stderr, "omniprompt: ignoring filter: {:#}", e)?,
+        }
+    }
+    let out_len = out.len();
+    if single_line {
+        SingleLine { inner: &mut *stdout, separator: &separator }.write_all(&out[..out_len])?;
+    } else {
+        stdout.write_all(&out[..out_len])?;
+    }
+    if debug_len {
+        let visible = String::from_utf8_lossy(&out[..out_len]);
+        let columns: Vec<String> = visible.lines().map(|l| colors::width(l).to_string()).collect();
+        writeln!(stderr, "omniprompt: {} bytes, {} visible columns", out_len, columns.join("/"))?;
+    }
+    Ok(())
+}
+
+/// `prompt` piped through the `filter` program, which is killed if it takes longer than `timeout`
+fn run_filter(filter: &str, prompt: &[u8], timeout: Duration) -> Result<Vec<u8>> {
+    use std::process::{Command, Stdio};
+    let mut child = Command::new(filter)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("running {}", filter))?;
+    // Feeding and draining the pipes on their own threads keeps a filter that fills one from blocking the other
+    let (mut stdin, mut stdout) = (child.stdin.take(), child.stdout.take());
+    let input = prompt.to_vec();
+    std::thread::spawn(move || stdin.as_mut().map(|stdin| stdin.write_all(&input)));
+    let reader = std::thread::spawn(move || {
+        let mut output = Vec::new();
+        stdout.as_mut().map(|stdout| std::io::Read::read_to_end(stdout, &mut output)).transpose().map(|_| output)
+    });
+    let deadline = std::time::Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if std::time::Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow!("{} took longer than {}ms", filter, timeout.as_millis()));
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    };
+    if !status.success() {
+        return Err(anyhow!("{} failed with {}", filter, status));
+    }
+    Ok(reader.join().map_err(|_| anyhow!("reading the output of {}", filter))??)
+}
+
+/// `omniprompt [exit code [duration ms]] [--option[=value]...]`
+///
+/// The shell has to time commands itself to pass their duration, e.g. in zsh (bash-preexec offers the same hooks):
+///
+/// ```zsh
+/// zmodload zsh/datetime zsh/mathfunc
+/// preexec() { omniprompt_start=$EPOCHREALTIME }
+/// precmd() {
+///     local rval=$? duration=
+///     [[ -n $omniprompt_start ]] && duration=$(( int((EPOCHREALTIME - omniprompt_start) * 1000) ))
+///     unset omniprompt_start
+///     PROMPT=$(omniprompt $rval $duration)
+/// }
+/// ```
+fn main() -> Result<()> {
+    let (positional, mut flags) = config::parse_args(std::env::args_os().skip(1))?;
+    let rval = positional.first().filter(|s|!s.is_empty()).map(|s|i32::from_str(s)).transpose()?;
+    if let Some(duration) = positional.get(1).filter(|s| !s.is_empty()) {
+        flags.entry("duration_ms".to_string()).or_insert_with(|| duration.clone());
+    }
+    print_default(rval, Config::load(flags), &mut std::io::stdout(), &mut std::io::stderr())
+}
+
+// Not comprehensive, but sanity checking
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    macro_rules! test {
+        ($name:ident, $field:expr) => {
+            #[test]
+            fn $name() {
+                {
+                    let stdout = std::io::stdout();
+                    setup(stdout.lock(), Some(0)).print_section($field).unwrap();
+                }
+                {
+                    let stdout = std::io::stdout();
+                    setup(stdout.lock(), Some(1)).print_section($field).unwrap();
+                }
+            }
+        }
+    }
+
+    fn setup<T: Write>(stream: T, rval: Option<i32>) -> FieldWriter<T> {
+        FieldWriter::new(stream, rval, Config::default())
+    }
+
+    /// A `Config` with only the given environment variables set
+    fn config(env: &[(&str, &str)]) -> Config {
+        Config::new(Default::default(), env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(), Default::default())
+    }
+
+    fn render(field: Field, env: &[(&str, &str)]) -> String {
+        FieldWriter::<Vec<u8>>::field_value(field, None, &config(env)).unwrap().text
+    }
+
+    /// Two rows of two sections each, without colors
+    fn render_rows(env: &[(&str, &str)]) -> String {
+        let mut fw = FieldWriter::new(Vec::new(), Some(0), config(env));
+        fw.print_section(Field::Prompt).unwrap();
+        fw.print_section(Field::Prompt).unwrap();
+        fw.print_line().unwrap();
+        fw.print_section(Field::Prompt).unwrap();
+        fw.print_section(Field::Prompt).unwrap();
+        colors::strip(&String::from_utf8(fw.stream).unwrap())
+    }
+
+    #[cfg(feature="asdf")]
+    test!(asdf, Field::Asdf);
+    #[cfg(feature="network")]
+    test!(bandwidth, Field::Bandwidth);
+    #[cfg(all(feature="battery", target_os="linux"))]
+    test!(battery, Field::Battery);
+    test!(exit_code, Field::ExitCode);
+    test!(file, Field::File);
+    #[cfg(feature="git")]
+    test!(git, Field::Git);
+    #[cfg(feature="git")]
+    test!(git_alias, Field::GitAlias);
+    #[cfg(feature="git")]
+    test!(git_behind, Field::GitBehind);
+    #[cfg(feature="git")]
+    test!(git_config, Field::GitConfig);
+    #[cfg(feature="git")]
+    test!(git_count, Field::GitCount);
+    #[cfg(feature="git")]
+    test!(git_owner, Field::GitOwner);
+    #[cfg(feature="git")]
+    test!(git_prs, Field::GitPrs);
+    #[cfg(feature="git")]
+    test!(git_remote, Field::GitRemote);
+    #[cfg(feature="git")]
+    test!(git_signature, Field::GitSignature);
+    #[cfg(feature="network")]
+    test!(network, Field::Network);
+    #[cfg(feature="platform")]
+    test!(platform, Field::Platform);
+    #[cfg(all(feature="platform", unix))]
+    test!(groups, Field::Groups);
+    test!(histnum, Field::HistNum);
+    #[cfg(feature="kube")]
+    test!(kube, Field::Kube);
+    #[cfg(feature="load")]
+    test!(load, Field::Load);
+    #[cfg(feature="patch")]
+    test!(patch, Field::Patch);
+    #[cfg(unix)]
+    test!(mail, Field::Mail);
+    #[cfg(target_os="linux")]
+    test!(mount, Field::Mount);
+    #[cfg(all(feature="network", target_os="linux"))]
+    test!(connections, Field::Connections);
+    #[cfg(feature="memory")]
+    test!(memory, Field::Memory);
+    #[cfg(feature="mise")]
+    test!(mise, Field::Mise);
+    test!(ppid, Field::Ppid);
+    test!(prompt, Field::Prompt);
+    test!(pwd, Field::Pwd);
+    test!(oldpwd, Field::OldPwd);
+    #[cfg(all(feature="platform", unix))]
+    test!(read_only, Field::ReadOnly);
+    test!(sandbox, Field::Sandbox);
+    #[cfg(all(feature="security", target_os="linux"))]
+    test!(security_context, Field::SecurityContext);
+    test!(session_time, Field::SessionTime);
+    test!(stdin, Field::Stdin);
+    #[cfg(all(feature="systemd", target_os="linux"))]
+    test!(systemd, Field::Systemd);
+    #[cfg(feature="platform")]
+    test!(temp, Field::Temp);
+    test!(time, Field::Time);
+    #[cfg(feature="tty")]
+    test!(tty, Field::Tty);
+    test!(whoami, Field::Whoami);
+    test!(wsl, Field::Wsl);
+
+    #[test]
+    fn locale_numbers() {
+        let grouped = |n: i64, locale: &str| locale_number(n, &config(&[("OMNIPROMPT_LOCALE_NUMBERS", "1"), ("LANG", locale)]));
+        assert_eq!(grouped(1234567, "en_US.UTF-8"), "1,234,567");
+        assert_eq!(grouped(1234567, "de_DE.UTF-8"), "1.234.567");
+        assert_eq!(grouped(-12345, "fr_FR"), "-12\u{a0}345");
+        assert_eq!(grouped(123, "en_US.UTF-8"), "123");
+        assert_eq!(grouped(1234567, "C"), "1234567");
+        assert_eq!(locale_number(1234567, &config(&[("LANG", "en_US.UTF-8")])), "1234567");
+        let lc_all = [("OMNIPROMPT_LOCALE_NUMBERS", "1"), ("LANG", "en_US.UTF-8"), ("LC_ALL", "de_DE.UTF-8")];
+        assert_eq!(locale_number(1234, &config(&lc_all)), "1.234");
+    }
+
+    #[test]
+    fn whoami_formats() {
+        let server = Some((IpAddr::from([10, 0, 0, 2]), 22));
+        let show = |template, server| colors::strip(&whoami_format(template, "me", "box.example.com", server));
+        assert_eq!(show("{host}:{user}", None), "box.example.com:me");
+        assert_eq!(show("{shorthost}", None), "box");
+        assert_eq!(show("{user}@{shorthost} via {ip}:{port}", server), "me@box via 10.0.0.2:22");
+        assert_eq!(colors::strip(&whoami_format("{shorthost}", "me", "localhost", None)), "localhost");
+        assert_eq!(ssh_server("10.0.0.1 51234 10.0.0.2 22").unwrap(), (IpAddr::from([10, 0, 0, 2]), 22));
+        assert!(ssh_server("10.0.0.1 51234").is_err());
+        let env = [("SSH_CONNECTION", "10.0.0.1 51234 10.0.0.2 22"), ("OMNIPROMPT_WHOAMI_FORMAT", "via {ip}:{port}")];
+        assert_eq!(colors::strip(&render(Field::Whoami, &env)), "via 10.0.0.2:22");
+    }
+
+    #[test]
+    fn histnum_set() {
+        assert!(render(Field::HistNum, &[("OMNIPROMPT_HISTNUM", "1234")]).contains("!1234"));
+    }
+
+    #[test]
+    fn histnum_unset() {
+        assert_eq!(render(Field::HistNum, &[]), "");
+    }
+
+    #[test]
+    fn session_time_set() {
+        let start = (Local::now().timestamp() - (3 * 3600 + 12 * 60 + 5)).to_string();
+        assert!(render(Field::SessionTime, &[("OMNIPROMPT_SESSION_START", &start)]).contains("3h12m"));
+    }
+
+    #[test]
+    fn session_time_unset() {
+        assert_eq!(render(Field::SessionTime, &[]), "");
+        assert_eq!(render(Field::SessionTime, &[("OMNIPROMPT_SESSION_START", "yesterday")]), "");
+    }
+
+    #[test]
+    fn time_ago() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("last_prompt");
+        let env = [("OMNIPROMPT_TIME_STYLE", "ago"), ("OMNIPROMPT_LAST_PROMPT_FILE", path.to_str().unwrap())];
+        assert_eq!(render(Field::Time, &env), "");
+        std::fs::write(&path, (Local::now().timestamp() - 125).to_string()).unwrap();
+        let rendered = render(Field::Time, &env);
+        assert!(rendered.contains("+2m"), "{}", rendered);
+        assert!(!rendered.contains(':'));
+        let recorded: i64 = std::fs::read_to_string(&path).unwrap().parse().unwrap();
+        assert!(Local::now().timestamp() - recorded < 60);
+    }
+
+    #[test]
+    fn time_dual() {
+        assert!(!colors::strip(&render(Field::Time, &[])).contains('('));
+        let rendered = colors::strip(&render(Field::Time, &[("OMNIPROMPT_TIME_DUAL", "1")]));
+        let (local, utc) = rendered.split_once(" (").unwrap();
+        assert!(local.contains(':'), "{:?}", rendered);
+        assert!(utc.ends_with("Z)"), "{:?}", rendered);
+        assert_eq!(utc.len(), "19:05:03Z)".len());
+    }
+
+    #[test]
+    fn time_width() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("last_prompt");
+        let env = [("OMNIPROMPT_TIME_STYLE", "ago"), ("OMNIPROMPT_TIME_WIDTH", "8"), ("OMNIPROMPT_LAST_PROMPT_FILE", path.to_str().unwrap())];
+        let render_since = |secs: i64| {
+            std::fs::write(&path, (Local::now().timestamp() - secs).to_string()).unwrap();
+            colors::strip(&render(Field::Time, &env))
+        };
+        let short = render_since(5);
+        let long = render_since(3 * 3600 + 12 * 60);
+        assert!(short.starts_with("+5s") || short.starts_with("+6s"), "{:?}", short);
+        assert_eq!(long.trim_end(), "+3h12m");
+        assert_eq!(short.chars().count(), 8);
+        assert_eq!(long.chars().count(), 8);
+    }
+
+    #[cfg(feature="asdf")]
+    #[test]
+    fn asdf_tool_versions() {
+        assert_eq!(tool_versions("# pinned runtimes\nnodejs 20.11.0 system\n\npython 3.12.1  # latest\n"), "nodejs@20.11.0 python@3.12.1");
+        assert_eq!(tool_versions("a 1\nb 2\nc 3\nd 4\ne 5\n"), "a@1 b@2 c@3 +2");
+        assert_eq!(tool_versions("incomplete\n"), "");
+    }
+
+    #[cfg(feature="mise")]
+    #[test]
+    fn mise_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("project");
+        std::fs::create_dir_all(project.join("src")).unwrap();
+        std::fs::write(dir.path().join(".tool-versions"), "ruby 3.3.0\n").unwrap();
+        assert_eq!(mise_tools(&project.join("src"), false), None);
+        assert_eq!(mise_tools(&project.join("src"), true), Some(vec![("ruby".to_string(), "3.3.0".to_string())]));
+
+        std::fs::write(project.join(".mise.toml"), concat!(
+            "[env]\nNODE_ENV = 'production'\n\n[tools]\nnode = \"20\"\npython = [\"3.12\", \"3.11\"]  # both\n",
+            "\"npm:prettier\" = { version = \"3.2.5\" }\n\n[settings]\nexperimental = true\n",
+        )).unwrap();
+        std::fs::write(project.join("mise.local.toml"), "[tools]\nnode = 'lts'\n").unwrap();
+        let tools = mise_tools(&project.join("src"), true).unwrap();
+        assert_eq!(tool_summary(&tools), "node@lts python@3.12 npm:prettier@3.2.5");
+        assert_eq!(mise_tools(dir.path(), false), None);
+    }
+
+    #[cfg(all(feature="battery", target_os="linux"))]
+    #[test]
+    fn battery_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let supply = |name: &str, files: &[(&str, &str)]| {
+            std::fs::create_dir(dir.path().join(name)).unwrap();
+            for (file, contents) in files {
+                std::fs::write(dir.path().join(name).join(file), format!("{}\n", contents)).unwrap();
+            }
+        };
+        assert_eq!(super::battery(dir.path()), None);
+        supply("AC", &[("type", "Mains"), ("online", "1")]);
+        assert_eq!(super::battery(dir.path()), None);
+        supply("BAT0", &[("type", "Battery"), ("capacity", "85"), ("status", "Charging")]);
+        assert_eq!(super::battery(dir.path()), Some((85, Some(true))));
+        assert_eq!(super::battery(&dir.path().join("missing")), None);
+
+        let render = |percent, charging| {
+            let mut out = Vec::new();
+            print_battery(&mut out, percent, charging).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert!(render(85, Some(true)).contains("\x1b[32m↑85%"));
+        assert!(render(35, Some(false)).contains("\x1b[33m↓35%"));
+        assert!(render(10, None).contains("\x1b[31m10%"));
+    }
+
+    #[cfg(all(feature="load", unix))]
+    #[test]
+    fn load_colors() {
+        let mut out = Vec::new();
+        print_load(&mut out, [0.52, 2.5, 4.25], 4).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(colors::strip(&out), "0.52 2.50 4.25");
+        assert!(out.contains("\x1b[32m0.52"));
+        assert!(out.contains("\x1b[33m2.50"));
+        assert!(out.contains("\x1b[31m4.25"));
+    }
+
+    #[cfg(feature="memory")]
+    #[test]
+    fn memory_usage() {
+        let render_as = |used, total, display| {
+            let mut out = Vec::new();
+            print_memory(&mut out, used, total, display).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let render = |used, total| render_as(used, total, MemDisplay::UsedTotal);
+        assert_eq!(render(8_100_000_000, 16_000_000_000), "8.1GB/16.0GB");
+        assert!(render(13_000_000_000, 16_000_000_000).contains("\x1b[33m13.0GB/16.0GB"));
+        assert!(render(15_000_000_000, 16_000_000_000).contains("\x1b[31m15.0GB/16.0GB"));
+        assert_eq!(render(1, 0), "");
+        assert_eq!(render_as(8_100_000_000, 16_000_000_000, MemDisplay::Used), "8.1GB");
+        assert_eq!(render_as(8_100_000_000, 16_000_000_000, MemDisplay::Percent), "51%");
+        assert!(render_as(15_000_000_000, 16_000_000_000, MemDisplay::Percent).contains("\x1b[31m94%"));
+    }
+
+    #[cfg(feature="memory")]
+    #[test]
+    fn mem_displays() {
+        assert_eq!(mem_display(&Config::default()).unwrap(), MemDisplay::UsedTotal);
+        assert_eq!(mem_display(&config(&[("OMNIPROMPT_MEM_DISPLAY", "used")])).unwrap(), MemDisplay::Used);
+        assert_eq!(mem_display(&config(&[("OMNIPROMPT_MEM_DISPLAY", "percent")])).unwrap(), MemDisplay::Percent);
+        assert!(mem_display(&config(&[("OMNIPROMPT_MEM_DISPLAY", "free")])).is_err());
+    }
+
+    #[cfg(feature="network")]
+    #[test]
+    fn network_directions() {
+        let (upload, download) = network_totals([(1000, 10), (2000, 20)].iter().copied());
+        assert_eq!((upload, download), (30, 3000));
+        let mut out = Vec::new();
+        print_network(&mut out, upload, download, &config(&[])).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "↑30↓3000");
+    }
+
+    #[cfg(feature="network")]
+    #[test]
+    fn network_rate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("omniprompt").join("network");
+        let sample = |eth0: (u64, u64), wlan0: (u64, u64)| vec![("eth0".to_string(), eth0.0, eth0.1), ("wlan0".to_string(), wlan0.0, wlan0.1)];
+        assert_eq!(network_rates(&path, 10_000, &sample((1000, 100), (0, 0))).unwrap(), None);
+        assert_eq!(network_rates(&path, 12_000, &sample((5000, 300), (2000, 100))).unwrap(), Some((150, 3000)));
+        // Interfaces without a previous sample are left out, and a clock going backwards gives no rate
+        assert_eq!(network_rates(&path, 13_000, &sample((5000, 300), (2000, 100))[..1]).unwrap(), Some((0, 0)));
+        assert_eq!(network_rates(&path, 12_000, &[("tun0".to_string(), 10, 10)]).unwrap(), None);
+        assert_eq!(network_rates(&path, 14_000, &[("tun0".to_string(), 20, 30)]).unwrap(), Some((10, 5)));
+    }
+
+    #[cfg(feature="network")]
+    #[test]
+    fn network_state_fallback() {
+        let (run, cache) = (PathBuf::from("/run/user/1000"), PathBuf::from("/Users/me/Library/Caches"));
+        assert_eq!(network_state_file(Some(run.clone()), Some(cache.clone())), run.join("omniprompt").join("network"));
+        // macOS and Windows have no runtime directory
+        assert_eq!(network_state_file(None, Some(cache.clone())), cache.join("omniprompt").join("network"));
+        assert_eq!(network_state_file(None, None), std::env::temp_dir().join("omniprompt").join("network"));
+    }
+
+    #[cfg(feature="network")]
+    #[test]
+    fn network_glyphs() {
+        let render = |env: &[(&str, &str)]| {
+            let mut out = Vec::new();
+            print_network(&mut out, "1 KB", "2 KB", &config(env)).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(render(&[]), "↑1 KB↓2 KB");
+        assert_eq!(render(&[("OMNIPROMPT_NET_UP_GLYPH", "up "), ("OMNIPROMPT_NET_DOWN_GLYPH", "down "), ("OMNIPROMPT_NET_SEP", " / ")]), "up 1 KB / down 2 KB");
+    }
+
+    #[cfg(feature="network")]
+    #[test]
+    fn bandwidth_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bandwidth");
+        let env = [("OMNIPROMPT_BANDWIDTH_CACHE", path.to_str().unwrap())];
+        assert_eq!(render(Field::Bandwidth, &env), "");
+        std::fs::write(&path, "download = 50.2\n").unwrap();
+        assert_eq!(colors::strip(&render(Field::Bandwidth, &env)), "↓50Mbps");
+        std::fs::write(&path, "download = 50\nupload = 10\n").unwrap();
+        assert_eq!(colors::strip(&render(Field::Bandwidth, &env)), "↓50Mbps ↑10Mbps");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn new_mail() {
+        use std::time::SystemTime;
+        let dir = tempfile::tempdir().unwrap();
+        let mailbox = dir.path().join("mbox");
+        std::fs::write(&mailbox, "From someone\n").unwrap();
+        let set_times = |accessed: SystemTime, modified: SystemTime| {
+            let times = std::fs::FileTimes::new().set_accessed(accessed).set_modified(modified);
+            std::fs::File::options().write(true).open(&mailbox).unwrap().set_times(times).unwrap();
+        };
+        let earlier = SystemTime::now() - Duration::from_secs(60);
+        set_times(earlier, SystemTime::now());
+        assert!(has_new_mail(std::slice::from_ref(&mailbox)));
+        assert!(has_new_mail(&[dir.path().join("missing"), PathBuf::from(format!("{}?You have mail", mailbox.display()))]));
+        let path = mailbox.to_str().unwrap();
+        assert!(render(Field::Mail, &[("MAIL", path)]).contains('✉'));
+        assert!(render(Field::Mail, &[("MAILPATH", &format!("{}?You have mail", path))]).contains('✉'));
+        assert_eq!(render(Field::Mail, &[("MAILPATH", "/nonexistent"), ("MAIL", path)]), "");
+        set_times(SystemTime::now(), earlier);
+        assert!(!has_new_mail(&[mailbox]));
+        assert!(!has_new_mail(&[]));
+    }
+
+    #[cfg(all(feature="security", target_os="linux"))]
+    #[test]
+    fn security_contexts() {
+        let dir = tempfile::tempdir().unwrap();
+        let attr = dir.path().join("current");
+        let missing = dir.path().join("missing");
+        let read = |contents: &str| {
+            std::fs::write(&attr, contents).unwrap();
+            super::security_context(&[&missing, &attr])
+        };
+        assert_eq!(read("unconfined_u:unconfined_r:unconfined_t:s0-s0:c0.c1023\0").as_deref(), Some("unconfined_t"));
+        assert_eq!(read("firefox (enforce)\n").as_deref(), Some("firefox"));
+        assert_eq!(read("unconfined\n"), None);
+        assert_eq!(super::security_context(&[&missing]), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stdin_kind() {
+        let render = |stdin| {
+            let mut out = Vec::new();
+            print_stdin(&mut out, stdin).unwrap();
+            colors::strip(&String::from_utf8(out).unwrap())
+        };
+        let (reader, _writer) = std::io::pipe().unwrap();
+        assert_eq!(render(std::fs::File::from(std::os::fd::OwnedFd::from(reader))), "pipe");
+        if let Ok(terminal) = std::fs::File::options().read(true).write(true).open("/dev/ptmx") {
+            assert_eq!(render(terminal), "tty");
+        }
+    }
+
+    #[cfg(feature="patch")]
+    #[test]
+    fn patches() {
+        let quilt = tempfile::tempdir().unwrap();
+        let subdir = quilt.path().join("src");
+        std::fs::create_dir_all(quilt.path().join("patches")).unwrap();
+        std::fs::create_dir_all(quilt.path().join(".pc")).unwrap();
+        std::fs::create_dir_all(&subdir).unwrap();
+        std::fs::write(quilt.path().join("patches").join("series"), "first.patch\nsecond.patch\nthird.patch\n").unwrap();
+        assert_eq!(top_patch(&subdir), None);
+        std::fs::write(quilt.path().join(".pc").join("applied-patches"), "first.patch\nsecond.patch\n").unwrap();
+        assert_eq!(top_patch(&subdir).as_deref(), Some("second.patch"));
+
+        let stgit = tempfile::tempdir().unwrap();
+        let git_dir = stgit.path().join(".git");
+        std::fs::create_dir_all(git_dir.join("patches").join("main")).unwrap();
+        std::fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        assert_eq!(top_patch(stgit.path()), None);
+        std::fs::write(git_dir.join("patches").join("main").join("applied"), "fix-typo\nadd-feature\n").unwrap();
+        assert_eq!(top_patch(stgit.path()).as_deref(), Some("add-feature"));
+    }
+
+    #[test]
+    fn wsl_detection() {
+        let linux = Some("Linux version 6.1.0-13-amd64 (debian-kernel@lists.debian.org)".to_string());
+        let wsl = Some("Linux version 5.15.133.1-microsoft-standard-WSL2".to_string());
+        assert_eq!(wsl_distro(Some("Ubuntu-22.04".to_string()), wsl.clone()).as_deref(), Some("Ubuntu-22.04"));
+        assert_eq!(wsl_distro(None, wsl).as_deref(), Some("WSL"));
+        assert_eq!(wsl_distro(Some(String::new()), linux.clone()), None);
+        assert_eq!(wsl_distro(None, linux), None);
+        assert_eq!(colors::strip(&render(Field::Wsl, &[("WSL_DISTRO_NAME", "Ubuntu-22.04")])), "Ubuntu-22.04");
+    }
+
+    #[cfg(all(feature="platform", unix))]
+    #[test]
+    fn read_only_flags() {
+        use nix::sys::statvfs::FsFlags;
+        let render = |flags| {
+            let mut out = Vec::new();
+            print_read_only(&mut out, flags).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(render(FsFlags::empty()), "");
+        assert_eq!(render(FsFlags::ST_NOSUID), "");
+        assert!(render(FsFlags::ST_RDONLY | FsFlags::ST_NOSUID).contains("\x1b[31mro"));
+    }
+
+    #[cfg(feature="kube")]
+    #[test]
+    fn kube_server() {
+        let kubeconfig = concat!(
+            "apiVersion: v1\nclusters:\n",
+            "- cluster:\n    certificate-authority-data: AAAA\n    server: https://api.prod.example.com:6443\n  name: prod\n",
+            "- cluster:\n    server: https://10.0.0.1\n  name: staging\n",
+            "contexts:\n",
+            "- context:\n    cluster: staging\n    user: admin\n  name: staging-admin\n",
+            "- context:\n    cluster: prod\n    namespace: web\n    user: admin\n  name: prod-admin\n",
+            "current-context: prod-admin\nkind: Config\n",
+        );
+        assert_eq!(kube_context(kubeconfig), Some(("prod-admin".to_string(), Some("api.prod.example.com".to_string()))));
+        let staging = kubeconfig.replace("current-context: prod-admin", "current-context: staging-admin");
+        assert_eq!(kube_context(&staging), Some(("staging-admin".to_string(), Some("10.0.0.1".to_string()))));
+        let missing = kubeconfig.replace("current-context: prod-admin", "current-context: gone");
+        assert_eq!(kube_context(&missing), Some(("gone".to_string(), None)));
+        assert_eq!(kube_context(&kubeconfig.replace("current-context: prod-admin", "current-context: \"\"")), None);
+        assert_eq!(kube_context("apiVersion: v1\n"), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config");
+        std::fs::write(&path, kubeconfig).unwrap();
+        let render = |show: &str| colors::strip(&render(Field::Kube, &[("KUBECONFIG", path.to_str().unwrap()), ("OMNIPROMPT_KUBE_SHOW", show)]));
+        assert_eq!(render("context"), "prod-admin");
+        assert_eq!(render("server"), "api.prod.example.com");
+        assert_eq!(render("both"), "prod-admin@api.prod.example.com");
+    }
+
+    #[test]
+    fn file_show() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".current-env");
+        let show = || colors::strip(&render(Field::File, &[("OMNIPROMPT_FILE_SHOW", path.to_str().unwrap())]));
+        assert_eq!(show(), "");
+        std::fs::write(&path, "  staging  \nsecond line\n").unwrap();
+        assert_eq!(show(), "staging");
+        std::fs::write(&path, "x".repeat(100_000)).unwrap();
+        assert_eq!(show(), format!("{}…", "x".repeat(FILE_SHOW_WIDTH)));
+        std::fs::write(&path, "\n").unwrap();
+        assert_eq!(show(), "");
+        assert_eq!(render(Field::File, &[]), "");
+    }
+
+    #[cfg(all(feature="platform", unix))]
+    #[test]
+    fn signal_exit_codes() {
+        let show = |code| colors::strip(&FieldWriter::<Vec<u8>>::field_value(Field::ExitCode, Some(code), &Config::default()).unwrap().text);
+        assert_eq!(show(130), "130 (SIGINT)");
+        assert_eq!(show(137), "137 (SIGKILL)");
+        assert_eq!(show(1), "1");
+        assert_eq!(show(128), "128");
+        assert_eq!(show(255), "255");
+    }
+
+    #[cfg(target_os="linux")]
+    #[test]
+    fn network_mounts() {
+        let mounts = concat!(
+            "/dev/nvme0n1p2 / ext4 rw,relatime 0 0\n",
+            "proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0\n",
+            "fileserver:/export/home /home/user nfs4 rw,relatime,vers=4.2 0 0\n",
+            "tmpfs /home/user/scratch tmpfs rw 0 0\n",
+            "//nas/share /mnt/my\\040share cifs rw 0 0\n",
+            "/dev/sdb1 /mnt/usb vfat rw 0 0\n",
+            "host:/over /mnt/usb nfs rw 0 0\n",
+        );
+        assert_eq!(network_mount(mounts, Path::new("/home/user/src")), Some("nfs4"));
+        assert_eq!(network_mount(mounts, Path::new("/home/user/scratch/build")), None);
+        assert_eq!(network_mount(mounts, Path::new("/home/username")), None);
+        assert_eq!(network_mount(mounts, Path::new("/mnt/my share/docs")), Some("cifs"));
+        assert_eq!(network_mount(mounts, Path::new("/mnt/usb")), Some("nfs"));
+        assert_eq!(network_mount(mounts, Path::new("/etc")), None);
+        assert_eq!(network_mount("", Path::new("/etc")), None);
+    }
+
+    #[cfg(all(feature="network", target_os="linux"))]
+    #[test]
+    fn tcp_table() {
+        let table = concat!(
+            "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n",
+            "   0: 0100007F:0277 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 1001 1 0000000000000000 100 0 0 10 0\n",
+            "   1: 0F02000A:D4B2 2C0F5A8C:01BB 01 00000000:00000000 02:000A7B1C 00000000  1000        0 1002 2 0000000000000000 20 4 30 10 -1\n",
+            "   2: 0F02000A:D4B4 2C0F5A8C:01BB 01 00000000:00000000 02:000A7B1C 00000000  1000        0 1003 2 0000000000000000 20 4 30 10 -1\n",
+            "   3: 0F02000A:D4B6 2C0F5A8C:01BB 06 00000000:00000000 03:00001234 00000000     0        0 0 3 0000000000000000\n",
+        );
+        let inodes = [1001, 1002, 0].iter().copied().collect();
+        assert_eq!(established_connections(table, &inodes), 1);
+        assert_eq!(established_connections(table, &[1002, 1003].iter().copied().collect()), 2);
+        assert_eq!(established_connections("", &inodes), 0);
+
+        assert_eq!(stat_session("4242 (my (odd) shell) S 4200 4242 4100 34816 4242 4194560"), Some(4100));
+        assert_eq!(stat_session("4242 (bash"), None);
+    }
+
+    #[test]
+    fn sandbox_detection() {
+        let info = "[Application]\nname=org.gnome.Terminal\nruntime=runtime/org.gnome.Platform\n\n[Instance]\nname=other\n";
+        assert_eq!(sandbox_app(&config(&[("FLATPAK_ID", "org.gnome.Console")]), Some(info)).as_deref(), Some("flatpak org.gnome.Console"));
+        assert_eq!(sandbox_app(&config(&[]), Some(info)).as_deref(), Some("flatpak org.gnome.Terminal"));
+        assert_eq!(sandbox_app(&config(&[("SNAP_NAME", "alacritty"), ("SNAP", "/snap/alacritty/42")]), None).as_deref(), Some("snap alacritty"));
+        assert_eq!(sandbox_app(&config(&[("SNAP", "/snap/alacritty/42")]), None).as_deref(), Some("snap alacritty"));
+        assert_eq!(sandbox_app(&config(&[("SNAP", "/opt/elsewhere")]), None), None);
+        assert_eq!(sandbox_app(&config(&[]), None), None);
+    }
+
+    #[test]
+    fn cache_ttl() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_dir = dir.path().to_str().unwrap();
+        let render = |histnum: &str, ttl: &str| {
+            let env = [("OMNIPROMPT_HISTNUM", histnum), ("OMNIPROMPT_CACHE_TTL_HISTNUM", ttl), ("OMNIPROMPT_CACHE_DIR", cache_dir)];
+            colors::strip(&FieldWriter::<Vec<u8>>::cached_field_value(Field::HistNum, None, &config(&env)).unwrap().text)
+        };
+        assert_eq!(render("1", "0"), "!1");
+        assert_eq!(render("2", "0"), "!2");
+        assert_eq!(render("3", "3600"), "!3");
+        assert_eq!(render("4", "3600"), "!3");
+        assert_eq!(render("5", "0"), "!5");
+
+        // Output styled for another shell, other colors or other options isn't reused
+        let render = |histnum: &str, extra: &[(&str, &str)]| {
+            let env = [&[("OMNIPROMPT_HISTNUM", histnum), ("OMNIPROMPT_CACHE_TTL_HISTNUM", "3600"), ("OMNIPROMPT_CACHE_DIR", cache_dir)], extra].concat();
+            FieldWriter::<Vec<u8>>::cached_field_value(Field::HistNum, None, &config(&env)).unwrap().text
+        };
+        colors::set_escapes(("%{", "%}"));
+        assert!(render("6", &[]).contains("%}!6"));
+        colors::set_escapes(("\\[", "\\]"));
+        assert!(render("7", &[]).contains("\\]!7"));
+        colors::set_choice(colors::Choice::Never);
+        assert_eq!(render("8", &[]), "!8");
+        assert_eq!(render("9", &[("OMNIPROMPT_INDENT", "2")]), "!9");
+        assert_eq!(render("10", &[("OMNIPROMPT_INDENT", "2")]), "!9");
+
+        // The TTL option is named by the field's key, underscores and all
+        let session = |hours: i64| {
+            let start = (Local::now().timestamp() - hours * 3600).to_string();
+            let env = [("OMNIPROMPT_SESSION_START", start.as_str()), ("OMNIPROMPT_CACHE_TTL_SESSION_TIME", "3600"), ("OMNIPROMPT_CACHE_DIR", cache_dir)];
+            FieldWriter::<Vec<u8>>::cached_field_value(Field::SessionTime, None, &config(&env)).unwrap().text
+        };
+        assert_eq!(session(1), "1h0m");
+        assert_eq!(session(2), "1h0m");
+        assert!(dir.path().read_dir().unwrap().any(|entry| entry.unwrap().file_name().to_string_lossy().starts_with("session_time-")));
+
+        // Nor is output that came from other environment variables
+        let oldpwd = |path: &str| {
+            let env = [("OLDPWD", path), ("OMNIPROMPT_CACHE_TTL_OLDPWD", "3600"), ("OMNIPROMPT_CACHE_DIR", cache_dir)];
+            FieldWriter::<Vec<u8>>::cached_field_value(Field::OldPwd, None, &config(&env)).unwrap().text
+        };
+        assert!(oldpwd("/first").contains("first"));
+        assert!(oldpwd("/second").contains("second"));
+        assert!(oldpwd("/first").contains("first"));
+    }
+
+    #[cfg(all(feature="systemd", target_os="linux"))]
+    #[test]
+    fn systemd_units() {
+        assert_eq!(systemd_unit("0::/user.slice/user-1000.slice/session-2.scope\n"), Some("session-2.scope"));
+        assert_eq!(systemd_unit("0::/user.slice/user-1000.slice/user@1000.service/app.slice/run-r1234.scope/sub\n"), Some("run-r1234.scope"));
+        assert_eq!(systemd_unit("12:pids:/user.slice\n1:name=systemd:/system.slice/sshd.service\n"), Some("sshd.service"));
+        assert_eq!(systemd_unit("0::/\n"), None);
+        assert_eq!(systemd_unit("0::/docker/0123abcd\n"), None);
+    }
+
+    #[cfg(all(feature="platform", unix))]
+    #[test]
+    fn group_names() {
+        use nix::unistd::Gid;
+        if let Ok(Some(root)) = nix::unistd::Group::from_gid(Gid::from_raw(0)) {
+            assert_eq!(group_name(Gid::from_raw(0)), root.name);
+        }
+        assert_eq!(group_name(Gid::from_raw(4_000_000_000)), "4000000000");
+        assert!(!group_name(nix::unistd::getegid()).is_empty());
+    }
+
+    #[test]
+    fn command_duration() {
+        let show = |millis: &str| colors::strip(&render(Field::Duration, &[("OMNIPROMPT_DURATION_MS", millis)]));
+        assert_eq!(show("450"), "450ms");
+        assert_eq!(show("1300"), "1.3s");
+        assert_eq!(show("124000"), "2m 4s");
+        assert_eq!(render(Field::Duration, &[]), "");
+        assert_eq!(colors::strip(&render(Field::Duration, &[("OMNIPROMPT_DURATION_MS", "1300"), ("OMNIPROMPT_DURATION_FORMAT", "ms")])), "1300ms");
+        assert!(FieldWriter::<Vec<u8>>::field_value(Field::Duration, None, &config(&[("OMNIPROMPT_DURATION_MS", "soon")])).is_err());
+    }
+
+    #[test]
+    fn duration() {
+        let secs = Duration::from_secs;
+        let millis = Duration::from_millis;
+        assert_eq!(format_duration(secs(45), DurationFormat::Coarse), "45s");
+        assert_eq!(format_duration(secs(12 * 60 + 59), DurationFormat::Coarse), "12m");
+        assert_eq!(format_duration(secs(3 * 3600 + 12 * 60), DurationFormat::Coarse), "3h12m");
+        assert_eq!(format_duration(millis(250), DurationFormat::Auto), "250ms");
+        assert_eq!(format_duration(millis(1234), DurationFormat::Auto), "1.2s");
+        assert_eq!(format_duration(secs(125), DurationFormat::Auto), "2m 5s");
+        assert_eq!(format_duration(millis(1234), DurationFormat::Seconds), "1s");
+        assert_eq!(format_duration(millis(1500), DurationFormat::Seconds), "2s");
+        assert_eq!(format_duration(millis(1234), DurationFormat::Millis), "1234ms");
+        assert_eq!(format_duration(secs(125), DurationFormat::Millis), "125000ms");
+    }
+
+    #[test]
+    fn duration_formats() {
+        assert_eq!(duration_format(&Config::default()).unwrap(), DurationFormat::Coarse);
+        assert_eq!(duration_format(&config(&[("OMNIPROMPT_DURATION_FORMAT", "auto")])).unwrap(), DurationFormat::Auto);
+        assert_eq!(duration_format(&config(&[("OMNIPROMPT_DURATION_FORMAT", "ms")])).unwrap(), DurationFormat::Millis);
+        assert!(duration_format(&config(&[("OMNIPROMPT_DURATION_FORMAT", "hours")])).is_err());
+    }
+
+    #[cfg(feature="platform")]
+    #[test]
+    fn temperature() {
+        let render = |celsius| {
+            let mut out = Vec::new();
+            print_temperature(&mut out, celsius).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert!(render(45.0).contains("\x1b[32m45°C"));
+        assert!(render(72.4).contains("\x1b[33m72°C"));
+        assert!(render(90.0).contains("\x1b[31m"));
+    }
+
+    #[test]
+    fn oldpwd_set() {
+        assert!(render(Field::OldPwd, &[("OLDPWD", "/var/log")]).contains("←/var/log"));
+        if let Some(home) = dirs::home_dir() {
+            let oldpwd = home.join("src").join("omniprompt");
+            assert!(render(Field::OldPwd, &[("OLDPWD", oldpwd.to_str().unwrap())]).contains("←~/src/omniprompt"));
+            assert!(render(Field::OldPwd, &[("OLDPWD", oldpwd.to_str().unwrap()), ("OMNIPROMPT_PWD_STYLE", "abbrev")]).contains("←~/s/omniprompt"));
+        }
+    }
+
+    #[test]
+    fn oldpwd_unset() {
+        assert_eq!(render(Field::OldPwd, &[]), "");
+    }
+
+    #[test]
+    fn pwd_home() {
+        let config = Config::default();
+        assert_eq!(super::pwd(Path::new("/home/user/src"), Some(Path::new("/home/user")), &config).unwrap(), "~/src");
+        assert_eq!(super::pwd(Path::new("/home/user"), Some(Path::new("/home/user")), &config).unwrap(), "~");
+        assert_eq!(super::pwd(Path::new("/tmp"), Some(Path::new("/home/user")), &config).unwrap(), "/tmp");
+    }
+
+    #[test]
+    fn pwd_styles() {
+        let render = |style: &str| super::pwd(Path::new("/home/user/dev/.config/projects/omniprompt"), Some(Path::new("/home/user")), &config(&[("OMNIPROMPT_PWD_STYLE", style)]));
+        assert_eq!(render("full").unwrap(), "~/dev/.config/projects/omniprompt");
+        assert_eq!(render("gradient").unwrap(), "~/dev/.config/projects/omniprompt");
+        assert_eq!(render("abbreviate").unwrap(), "~/d/.c/p/omniprompt");
+        assert_eq!(render("abbrev").unwrap(), "~/d/.c/p/omniprompt");
+        assert_eq!(render("truncate").unwrap(), "~/dev/…/projects/omniprompt");
+        assert!(render("tiny").is_err());
+    }
+
+    #[test]
+    fn pwd_truncate() {
+        assert_eq!(truncate_components("~/src/omniprompt/src/bin", 3), "~/src/…/src/bin");
+        assert_eq!(truncate_components("/usr/local/lib/python3/site-packages", 4), "/usr/…/lib/python3/site-packages");
+        assert_eq!(truncate_components("~/src/omniprompt/src", 3), "~/src/omniprompt/src");
+        assert_eq!(truncate_components("omniprompt:a/b/c", 2), "omniprompt:a/…/c");
+        assert_eq!(truncate_components("/usr/local/lib", 1), "…/lib");
+        assert_eq!(truncate_components("/usr/local/lib", 0), "…/lib");
+        assert_eq!(truncate_components("~/a/b", 1), "…/b");
+        assert_eq!(truncate_components("~", 1), "~");
+        assert_eq!(truncate_components("/", 1), "/");
+        let config = config(&[("OMNIPROMPT_PWD_MAX_COMPONENTS", "3"), ("OMNIPROMPT_PWD_STYLE", "abbrev")]);
+        assert_eq!(super::pwd(Path::new("/home/user/projects/a/crate/src"), Some(Path::new("/home/user")), &config).unwrap(), "~/p/…/c/src");
+        assert_eq!(super::pwd(Path::new("/home/user/a/b/c/d"), Some(Path::new("/home/user")), &Config::default()).unwrap(), "~/a/b/c/d");
+        assert!(super::pwd(Path::new("/"), None, &super::test::config(&[("OMNIPROMPT_PWD_MAX_COMPONENTS", "few")])).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pwd_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().canonicalize().unwrap().join("real");
+        std::fs::create_dir(&real).unwrap();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+        assert_eq!(logical_pwd(link.to_str(), &real), Some(link.clone()));
+        assert_eq!(logical_pwd(real.to_str(), &real), None);
+        assert_eq!(logical_pwd(dir.path().to_str(), &real), None);
+        assert_eq!(logical_pwd(None, &real), None);
+    }
+
+    #[cfg(feature="git")]
+    #[test]
+    fn pwd_repo() {
+        let dir = git::test::repo();
+        let root = dir.path().canonicalize().unwrap();
+        let subdir = root.join("services").join("foo");
+        std::fs::create_dir_all(&subdir).unwrap();
+        let config = config(&[("OMNIPROMPT_PWD_REPO", "1")]);
+        let name = root.file_name().unwrap().to_string_lossy();
+        assert_eq!(super::pwd(&subdir, None, &config).unwrap(), format!("{}:services/foo", name));
+        assert_eq!(super::pwd(&subdir, None, &Config::default()).unwrap(), subdir.display().to_string());
+        assert_eq!(super::pwd(Path::new("/"), None, &config).unwrap(), "/");
+    }
+
+    #[test]
+    fn default() {
+        print_default(Some(0), Config::default(), &mut std::io::stdout(), &mut std::io::stderr()).unwrap();
+        print_default(Some(1), Config::default(), &mut std::io::stdout(), &mut std::io::stderr()).unwrap();
+    }
+
+    #[test]
+    fn debug_len() {
+        let mut stderr = Vec::new();
+        print_default(Some(0), Config::default(), &mut Vec::new(), &mut stderr).unwrap();
+        assert!(stderr.is_empty());
+        let mut stdout = Vec::new();
+        print_default(Some(0), config(&[("OMNIPROMPT_DEBUG_LEN", "1")]), &mut stdout, &mut stderr).unwrap();
+        let stderr = String::from_utf8(stderr).unwrap();
+        assert!(stderr.starts_with(&format!("omniprompt: {} bytes, ", stdout.len())), "{}", stderr);
+        assert!(stderr.ends_with(" visible columns\n"));
+    }
+
+    #[test]
+    fn separators() {
+        assert_eq!(render_rows(&[]), "┌─[$]>  - [$]> \n└─[$]> -[$]> ");
+        assert_eq!(render_rows(&[("OMNIPROMPT_SEP_ROW0", " | "), ("OMNIPROMPT_SEP_ROWN", "~")]), "┌─[$]>  | [$]> \n└─[$]> ~[$]> ");
+    }
+
+    #[test]
+    fn max_rows() {
+        let render = |env: &[(&str, &str)]| {
+            let mut fw = FieldWriter::new(Vec::new(), Some(0), config(env));
+            for _ in 0..3 {
+                fw.print_section(Field::Prompt).unwrap();
+                fw.print_line().unwrap();
+            }
+            fw.print_section(Field::Prompt).unwrap();
+            colors::strip(&String::from_utf8(fw.stream).unwrap())
+        };
+        assert_eq!(render(&[]).lines().count(), 4);
+        assert_eq!(render(&[("OMNIPROMPT_MAXROWS", "2")]), "┌─[$]> \n└─[$]> -[$]> -[$]> ");
+        assert_eq!(render(&[("OMNIPROMPT_MAXROWS", "1")]).lines().count(), 1);
+    }
+
+    #[test]
+    fn safe() {
+        assert_eq!(render_rows(&[("OMNIPROMPT_SAFE", "1")]), render_rows(&[]));
+        #[cfg(feature="git")]
+        {
+            let mut fw = FieldWriter::new(Vec::new(), Some(0), config(&[("OMNIPROMPT_SAFE", "1")]));
+            fw.print_section(Field::GitSignature).unwrap();
+            assert!(fw.stream.is_empty());
+        }
+    }
+
+    #[test]
+    fn indent() {
+        let rendered = render_rows(&[("OMNIPROMPT_INDENT", "2")]);
+        assert_eq!(rendered, "  ┌─[$]>  - [$]> \n  └─[$]> -[$]> ");
+        let mut fw = FieldWriter::new(Vec::new(), Some(0), config(&[("OMNIPROMPT_INDENT", "3")]));
+        fw.print_section(Field::Prompt).unwrap();
+        assert!(String::from_utf8(fw.stream).unwrap().starts_with("   \x1b["));
+    }
+
+    #[test]
+    fn empty_fields_separators() {
+        let mut fw = FieldWriter::new(Vec::new(), Some(0), config(&[("OMNIPROMPT_SEP_ROW0", " | ")]));
+        for field in [Field::HistNum, Field::ExitCode, Field::OldPwd, Field::SessionTime, Field::HistNum, Field::Prompt].iter() {
+            fw.print_section(*field).unwrap();
+        }
+        assert_eq!(colors::strip(&String::from_utf8(fw.stream).unwrap()), "┌─[0] | [$]> ");
+    }
+
+    #[test]
+    fn frame() {
+        assert_eq!(render_rows(&[("OMNIPROMPT_FRAME", "rounded")]), "╭─[$]>  - [$]> \n╰─[$]> -[$]> ");
+        assert_eq!(render_rows(&[("OMNIPROMPT_FRAME", "square")]), render_rows(&[]));
+    }
+
+    #[test]
+    fn pwd_abbrev() {
+        assert_eq!(abbreviate("/usr/local/lib/python3/site-packages/foo"), "/u/l/l/p/s/foo");
+        assert_eq!(abbreviate("~/.config/omniprompt"), "~/.c/omniprompt");
+        assert_eq!(abbreviate("/"), "/");
+        assert_eq!(abbreviate("~"), "~");
+    }
+
+    #[test]
+    fn pwd_gradient() {
+        let rendered = gradient("~/src/omniprompt/src");
+        let escapes: std::collections::HashSet<&str> = rendered.split("\x1b[38;2;").skip(1).map(|s| s.split('m').next().unwrap()).collect();
+        assert_eq!(escapes.len(), 4, "{:?}", rendered);
+        assert!(rendered.contains("\x1b[38;2;255;215;0m"));
+        assert!(rendered.contains("\x1b[38;2;255;0;175m"));
+        assert_eq!(colors::strip(&rendered), "~/src/omniprompt/src");
+    }
+
+    #[test]
+    fn accessible() {
+        let config = config(&[("OMNIPROMPT_ACCESSIBLE", "1"), ("OMNIPROMPT_HISTNUM", "12")]);
+        configure_colors(&config).unwrap();
+        let mut fw = FieldWriter::new(Vec::new(), Some(0), config);
+        fw.print_section(Field::ExitCode).unwrap();
+        fw.print_section(Field::HistNum).unwrap();
+        fw.print_line().unwrap();
+        fw.print_section(Field::Prompt).unwrap();
+        assert_eq!(String::from_utf8(fw.stream).unwrap(), "exit code: 0, history: !12\n$ ");
+        colors::set_plain(false);
+        assert_eq!(strip_icons("⚠DETACHED ⟳3"), "DETACHED 3");
+    }
+
+    #[test]
+    fn anchor_bottom() {
+        let mut stdout = Vec::new();
+        print_default(Some(0), Config::default(), &mut stdout, &mut Vec::new()).unwrap();
+        assert!(!String::from_utf8(stdout).unwrap().contains(ANCHOR_BOTTOM));
+        let mut stdout = Vec::new();
+        print_default(Some(0), config(&[("OMNIPROMPT_ANCHOR_BOTTOM", "1")]), &mut stdout, &mut Vec::new()).unwrap();
+        let stdout = String::from_utf8(stdout).unwrap();
+        assert!(stdout.starts_with(&colors::non_printing(ANCHOR_BOTTOM)), "{:?}", stdout);
+        assert!(!colors::strip(&stdout).contains(ANCHOR_BOTTOM));
+    }
+
+    #[test]
+    fn banner() {
+        let render_lines = |env: &[(&str, &str)]| {
+            let mut stdout = Vec::new();
+            print_default(Some(0), config(env), &mut stdout, &mut Vec::new()).unwrap();
+            String::from_utf8(stdout).unwrap().lines().map(str::to_string).collect::<Vec<_>>()
+        };
+        assert!(render_lines(&[]).first().unwrap().contains("┌─["));
+        assert!(render_lines(&[("OMNIPROMPT_BANNER", "")]).first().unwrap().contains("┌─["));
+        let lines = render_lines(&[("OMNIPROMPT_BANNER", "⚠ PRODUCTION ⚠")]);
+        assert!(lines[0].contains("\x1b[31m"), "{:?}", lines[0]);
+        assert_eq!(colors::strip(&lines[0]), "⚠ PRODUCTION ⚠");
+        assert!(lines[1].contains("┌─["));
+        let lines = render_lines(&[("OMNIPROMPT_BANNER", "staging"), ("OMNIPROMPT_BANNER_COLOR", "0")]);
+        assert_eq!(lines[0], "staging");
+    }
+
+    #[test]
+    fn single_line() {
+        let mut stdout = Vec::new();
+        print_default(Some(0), config(&[("OMNIPROMPT_SINGLE_LINE", "1"), ("OMNIPROMPT_BANNER", "banner")]), &mut stdout, &mut Vec::new()).unwrap();
+        assert!(!stdout.contains(&b'\n'));
+        let stdout = colors::strip(&String::from_utf8(stdout).unwrap());
+        assert!(stdout.starts_with("banner\\n┌─["), "{}", stdout);
+        assert!(stdout.contains("\\n└─["));
+        let mut stdout = Vec::new();
+        print_default(Some(0), config(&[("OMNIPROMPT_SINGLE_LINE", "1"), ("OMNIPROMPT_SINGLE_LINE_SEPARATOR", " ⏎ ")]), &mut stdout, &mut Vec::new()).unwrap();
+        assert!(colors::strip(&String::from_utf8(stdout).unwrap()).contains(" ⏎ └─["));
+    }
+
+    #[test]
+    fn iterm_statusbar() {
+        let mut stdout = Vec::new();
+        print_default(Some(0), config(&[("OMNIPROMPT_FORMAT", "iterm-statusbar"), ("OMNIPROMPT_HISTNUM", "12")]), &mut stdout, &mut Vec::new()).unwrap();
+        let stdout = String::from_utf8(stdout).unwrap();
+        assert!(stdout.contains(&format!("\x1b]1337;SetUserVar=omniprompt_exit_code={}\x07", base64(b"0"))), "{:?}", stdout);
+        assert!(stdout.contains(&format!("\x1b]1337;SetUserVar=omniprompt_history={}\x07", base64(b"!12"))));
+        assert!(!stdout.contains('\n'));
+        assert!(!stdout.contains("┌─["));
+        assert!(print_default(Some(0), config(&[("OMNIPROMPT_FORMAT", "xml")]), &mut Vec::new(), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn shell_names() {
+        assert_eq!(colors::shell_name("-zsh"), "zsh");
+        assert_eq!(colors::shell_name("bash"), "bash");
+        assert_eq!(colors::shell_name("pwsh.exe"), "pwsh");
+        assert!(colors::parent_shell().is_some());
+    }
+
+    #[test]
+    fn shell_override() {
+        let render = |shell: &str| {
+            let mut stdout = Vec::new();
+            let env = [("OMNIPROMPT_SHELL", shell), ("OMNIPROMPT_FIELDS", "histnum"), ("OMNIPROMPT_HISTNUM", "12")];
+            print_default(Some(0), config(&env), &mut stdout, &mut Vec::new()).map(|()| String::from_utf8(stdout).unwrap())
+        };
+        assert!(render("zsh").unwrap().starts_with("%{\x1b[1m%}%{\x1b[31m%}┌─["));
+        assert!(render("bash").unwrap().starts_with("\\[\x1b[1m\\]\\[\x1b[31m\\]┌─["));
+        let fish = render("fish").unwrap();
+        assert!(fish.starts_with("\x1b[1m\x1b[31m┌─["), "{:?}", fish);
+        assert_eq!(colors::width(&fish), 7);
+        assert!(render("tcsh").is_err());
+    }
+
+    #[test]
+    fn markers() {
+        let render = |env: &[(&str, &str)]| {
+            let mut stdout = Vec::new();
+            print_default(Some(0), config(env), &mut stdout, &mut Vec::new()).map(|()| String::from_utf8(stdout).unwrap())
+        };
+        let rendered = render(&[("OMNIPROMPT_PROMPT_MARKERS", "osc133")]).unwrap();
+        assert!(rendered.starts_with(&colors::non_printing("\x1b]133;A\x07")), "{:?}", rendered);
+        assert!(rendered.ends_with(&colors::non_printing("\x1b]133;B\x07")), "{:?}", rendered);
+        assert!(colors::strip(&rendered).ends_with("]> "));
+        let rendered = render(&[("OMNIPROMPT_PROMPT_MARKERS", "fig"), ("OMNIPROMPT_COLOR", "never")]).unwrap();
+        assert!(rendered.starts_with("\x1b]697;NewCmd\x07\x1b]697;StartPrompt\x07┌─["), "{:?}", rendered);
+        assert!(rendered.ends_with("]> \x1b]697;EndPrompt\x07"), "{:?}", rendered);
+        assert!(!render(&[]).unwrap().contains("\x1b]133;"));
+        assert!(!render(&[("OMNIPROMPT_PROMPT_MARKERS", "osc133"), ("OMNIPROMPT_FORMAT", "iterm-statusbar")]).unwrap().contains("\x1b]133;"));
+        assert!(render(&[("OMNIPROMPT_PROMPT_MARKERS", "warp")]).is_err());
+    }
+
+    #[test]
+    fn color_hints() {
+        let hint = |field, exit_code| FieldWriter::<Vec<u8>>::field_value(field, exit_code, &config(&[("OMNIPROMPT_HISTNUM", "7")])).unwrap().color;
+        assert_eq!(hint(Field::ExitCode, Some(0)).as_deref(), Some("green"));
+        assert_eq!(hint(Field::ExitCode, Some(1)).as_deref(), Some("red"));
+        assert_eq!(hint(Field::ExitCode, None), None);
+        assert_eq!(hint(Field::HistNum, None).as_deref(), Some("dim"));
+        // Bold isn't a color
+        assert_eq!(hint(Field::Prompt, None).as_deref(), Some("magenta"));
+        colors::set_choice(colors::Choice::Never);
+        assert_eq!(FieldWriter::<Vec<u8>>::field_value(Field::ExitCode, Some(2), &Config::default()).unwrap(), FieldValue { text: "2".to_string(), color: Some("red".to_string()) });
+    }
+
+    #[test]
+    fn json() {
+        let render = |env: &[(&str, &str)]| {
+            let mut stdout = Vec::new();
+            print_default(Some(3), config(env), &mut stdout, &mut Vec::new()).unwrap();
+            String::from_utf8(stdout).unwrap()
+        };
+        let env = [("OMNIPROMPT_FORMAT", "json"), ("OMNIPROMPT_FIELDS", "histnum | exit_code, prompt"), ("OMNIPROMPT_HISTNUM", "12"), ("OMNIPROMPT_COLOR_HISTNUM", "nope")];
+        assert_eq!(render(&env), concat!(
+            r#"[{"name":"histnum","value":"!12","color":"dim","error":"parsing color_histnum \"nope\": unknown color"},"#,
+            r#"{"name":"exit_code","value":"3","color":"red","error":null}]"#, "\n",
+        ));
+        let env = [("OMNIPROMPT_JSON", "1"), ("OMNIPROMPT_FIELDS", "exit_code"), ("OMNIPROMPT_PROMPT_MARKERS", "osc133"), ("OMNIPROMPT_FIELD_OFFSETS", "1")];
+        assert_eq!(render(&env), "[{\"name\":\"exit_code\",\"value\":\"3\",\"color\":\"red\",\"error\":null}]\n");
+        // Everything that decorates a prompt is left out
+        let env = [
+            ("OMNIPROMPT_FORMAT", "json"), ("OMNIPROMPT_BANNER", "Welcome"), ("OMNIPROMPT_ANCHOR_BOTTOM", "1"), ("OMNIPROMPT_SHELL", "bash"),
+            ("OMNIPROMPT_SINGLE_LINE", "1"), ("OMNIPROMPT_ROW_BANDS", "1"), ("OMNIPROMPT_FIELDS", "histnum | exit_code"), ("OMNIPROMPT_HISTNUM", "12"),
+        ];
+        let rendered = render(&env);
+        let parsed: serde_json::Value = serde_json::from_str(&rendered).unwrap();
+        assert_eq!(parsed.as_array().map(Vec::len), Some(2), "{:?}", rendered);
+        assert!(!rendered.contains("Welcome") && !rendered.contains('\x1b') && !rendered.contains("\\["), "{:?}", rendered);
+        // The names can be fed back into `fields`
+        let parsed: serde_json::Value = serde_json::from_str(&render(&[("OMNIPROMPT_JSON", "1"), ("OMNIPROMPT_HISTNUM", "12")])).unwrap();
+        for record in parsed.as_array().unwrap() {
+            let name = record["name"].as_str().unwrap();
+            assert_eq!(Field::from_name(name).unwrap().key(), name);
+        }
+        let env = [("OMNIPROMPT_JSON", "1"), ("OMNIPROMPT_FIELDS", "exit_code"), ("OMNIPROMPT_COLOR_EXIT_CODE", "blue")];
+        assert_eq!(render(&env), "[{\"name\":\"exit_code\",\"value\":\"3\",\"color\":\"blue\",\"error\":null}]\n");
+    }
+
+    #[test]
+    fn base64_encoding() {
+        assert_eq!(super::base64(b""), "");
+        assert_eq!(super::base64(b"f"), "Zg==");
+        assert_eq!(super::base64(b"fo"), "Zm8=");
+        assert_eq!(super::base64(b"foo"), "Zm9v");
+        assert_eq!(super::base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn color_choice() {
+        let render = |color: &str| {
+            let mut stdout = Vec::new();
+            print_default(Some(0), config(&[("OMNIPROMPT_COLOR", color)]), &mut stdout, &mut Vec::new()).unwrap();
+            String::from_utf8(stdout).unwrap()
+        };
+        assert!(render("always").contains('\x1b'));
+        assert!(!render("never").contains('\x1b'));
+        // Follows whether the test's stdout happens to be a terminal
+        assert_eq!(render("auto").contains('\x1b'), supports_color::on_cached(supports_color::Stream::Stdout).is_some());
+        colors::set_choice(colors::Choice::Always);
+        assert!(print_default(Some(0), config(&[("OMNIPROMPT_COLOR", "sometimes")]), &mut Vec::new(), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn no_color() {
+        let render = |env: &[(&str, &str)]| {
+            let mut stdout = Vec::new();
+            print_default(Some(0), config(env), &mut stdout, &mut Vec::new()).unwrap();
+            String::from_utf8(stdout).unwrap()
+        };
+        assert!(!render(&[("NO_COLOR", "1")]).contains('\x1b'));
+        assert!(render(&[("NO_COLOR", "")]).contains('\x1b'));
+        assert!(render(&[("NO_COLOR", "1"), ("FORCE_COLOR", "1")]).contains('\x1b'));
+        assert!(!render(&[("NO_COLOR", "1"), ("FORCE_COLOR", "0")]).contains('\x1b'));
+        assert!(!render(&[("NO_COLOR", "1"), ("FORCE_COLOR", "false")]).contains('\x1b'));
+        assert!(render(&[("NO_COLOR", "1"), ("OMNIPROMPT_COLOR", "always")]).contains('\x1b'));
+        assert!(!render(&[("FORCE_COLOR", "1"), ("OMNIPROMPT_COLOR", "never")]).contains('\x1b'));
+    }
+
+    #[test]
+    fn no_color_flag() {
+        let render = |env: &[(&str, &str)]| {
+            let mut stdout = Vec::new();
+            print_default(Some(0), config(env), &mut stdout, &mut Vec::new()).unwrap();
+            String::from_utf8(stdout).unwrap()
+        };
+        let rendered = render(&[("OMNIPROMPT_NO_COLOR", "1"), ("FORCE_COLOR", "1")]);
+        assert!(!rendered.contains('\x1b'), "{:?}", rendered);
+        assert!(rendered.starts_with("┌─[") && rendered.contains("\n└─[") && rendered.ends_with("]> "), "{:?}", rendered);
+        assert!(render(&[("OMNIPROMPT_NO_COLOR", "1"), ("OMNIPROMPT_COLOR", "always")]).contains('\x1b'));
+    }
+
+    #[test]
+    fn plain() {
+        let render = |env: &[(&str, &str)]| {
+            let mut stdout = Vec::new();
+            print_default(Some(1), config(env), &mut stdout, &mut Vec::new()).unwrap();
+            String::from_utf8(stdout).unwrap()
+        };
+        let options = [("OMNIPROMPT_SHELL", "zsh"), ("OMNIPROMPT_PROMPT_MARKERS", "osc133"), ("OMNIPROMPT_ROW_BANDS", "1"), ("OMNIPROMPT_FIELD_OFFSETS", "1")];
+        let rendered = render(&options);
+        assert!(rendered.contains('\x1b') && rendered.contains("%{"));
+        let rendered = render(&[&options[..], &[("OMNIPROMPT_PLAIN", "1"), ("OMNIPROMPT_COLOR", "always")]].concat());
+        assert!(!rendered.contains('\x1b') && !rendered.contains("%{"), "{:?}", rendered);
+        assert!(rendered.contains("[1]") && rendered.ends_with("]> "), "{:?}", rendered);
+    }
+
+    #[test]
+    fn row_bands() {
+        let mut fw = FieldWriter::new(Vec::new(), Some(0), config(&[("OMNIPROMPT_ROW_BANDS", "1"), ("COLUMNS", "20")]));
+        fw.print_section(Field::ExitCode).unwrap();
+        fw.print_section(Field::ExitCode).unwrap();
+        fw.print_line().unwrap();
+        fw.print_section(Field::Prompt).unwrap();
+        fw.close_band(false).unwrap();
+        let rendered = String::from_utf8(fw.stream).unwrap();
+        let rows: Vec<&str> = rendered.lines().collect();
+        assert!(rows[0].starts_with("\x1b[48;5;236m") && rows[0].ends_with("\x1b[49m"), "{:?}", rows[0]);
+        assert_eq!(colors::strip(rows[0]).chars().count(), 20);
+        assert!(rows[1].starts_with("\x1b[48;5;238m") && rows[1].ends_with("\x1b[22m\x1b[49m"), "{:?}", rows[1]);
+        assert!(!render_rows(&[]).contains("\x1b[48"));
+    }
+
+    #[test]
+    fn field_colors() {
+        let mut fw = FieldWriter::new(Vec::new(), Some(0), config(&[("OMNIPROMPT_COLOR_EXIT_CODE", "cyan")]));
+        fw.print_section(Field::ExitCode).unwrap();
+        let rendered = String::from_utf8(fw.stream).unwrap();
+        assert!(rendered.contains("\x1b[36m0\x1b[39m"), "{:?}", rendered);
+        assert!(!rendered.contains("\x1b[32m"));
+        let mut fw = FieldWriter::new(Vec::new(), Some(0), config(&[("OMNIPROMPT_COLOR_EXIT_CODE", "mauve")]));
+        fw.print_section(Field::ExitCode).unwrap();
+        assert!(fw.errors.contains("unknown color"), "{}", fw.errors);
+    }
+
+    #[test]
+    fn field_list() {
+        let render = |fields: &str| {
+            let mut stdout = Vec::new();
+            let env = [("OMNIPROMPT_FIELDS", fields), ("OMNIPROMPT_HISTNUM", "12"), ("OMNIPROMPT_COLOR", "never")];
+            print_default(Some(3), config(&env), &mut stdout, &mut Vec::new()).map(|()| String::from_utf8(stdout).unwrap())
+        };
+        let rendered = render("histnum, exit_code").unwrap();
+        assert_eq!(rendered, "┌─[!12] - [3]");
+        assert_eq!(render("prompt,exit_code").unwrap(), "┌─[3] - [$]> ");
+        assert_eq!(render("histnum | exit_code, prompt").unwrap(), "┌─[!12]\n└─[3]-[$]> ");
+        assert_eq!(render("histnum > exit_code | prompt").unwrap(), "┌─[!12] - [3]\n└─[$]> ");
+        let error = format!("{:#}", render("pwd,nonsense").unwrap_err());
+        assert!(error.contains("unknown field \"nonsense\""), "{}", error);
+        #[cfg(not(feature="tty"))]
+        assert!(format!("{:#}", render("tty").unwrap_err()).contains("needs the tty feature"));
+    }
+
+    #[test]
+    fn right_aligned() {
+        let render = |fields: &str, columns: &str| {
+            let mut stdout = Vec::new();
+            let env = [("OMNIPROMPT_FIELDS", fields), ("OMNIPROMPT_HISTNUM", "12"), ("OMNIPROMPT_FILE_SHOW", "/nonexistent"), ("COLUMNS", columns)];
+            print_default(Some(3), config(&env), &mut stdout, &mut Vec::new()).unwrap();
+            colors::strip(&String::from_utf8(stdout).unwrap())
+        };
+        assert_eq!(render("histnum > exit_code | prompt", "20"), "┌─[!12]          [3]\n└─[$]> ");
+        assert_eq!(render("histnum > file, exit_code, histnum", "30"), "┌─[!12]            [3] - [!12]");
+        // Too narrow for the right group to fit, or a terminal width that isn't known
+        assert_eq!(render("histnum > exit_code", "8"), "┌─[!12] - [3]");
+        assert_eq!(render("histnum > exit_code", ""), "┌─[!12] - [3]");
+        // Nothing on the left to align against
+        assert_eq!(render("> exit_code", "20"), "┌─[3]");
+        assert_eq!(render("histnum > file | exit_code", "20"), "┌─[!12]\n└─[3]");
+
+        let mut stdout = Vec::new();
+        let env = [("OMNIPROMPT_FIELDS", "histnum > histnum"), ("OMNIPROMPT_HISTNUM", "12"), ("COLUMNS", "20"), ("OMNIPROMPT_FIELD_OFFSETS", "1")];
+        print_default(Some(0), config(&env), &mut stdout, &mut Vec::new()).unwrap();
+        let stdout = String::from_utf8(stdout).unwrap();
+        let (rendered, trailer) = stdout.split_once(&format!("\x1b]{};", OFFSETS_OSC)).unwrap();
+        let start: usize = trailer.rsplit("[\"histnum\",").next().unwrap().split(',').next().unwrap().parse().unwrap();
+        assert!(colors::strip(&rendered[start..]).starts_with("!12]"), "{:?}", &rendered[start..]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn filter() {
+        let render = |filter: &str| {
+            let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+            let env = [("OMNIPROMPT_FILTER", filter), ("OMNIPROMPT_FIELDS", "histnum"), ("OMNIPROMPT_HISTNUM", "12"), ("OMNIPROMPT_FILTER_TIMEOUT_MS", "500")];
+            print_default(Some(0), config(&env), &mut stdout, &mut stderr).unwrap();
+            (colors::strip(&String::from_utf8(stdout).unwrap()), String::from_utf8(stderr).unwrap())
+        };
+        assert_eq!(render("cat"), ("┌─[!12]".to_string(), String::new()));
+        let (rendered, warning) = render("false");
+        assert_eq!(rendered, "┌─[!12]");
+        assert!(warning.starts_with("omniprompt: ignoring filter: false failed"), "{}", warning);
+        assert!(render("/nonexistent/filter").1.contains("running /nonexistent/filter"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let slow = dir.path().join("slow");
+        std::fs::write(&slow, "#!/bin/sh\nexec sleep 5\n").unwrap();
+        std::fs::set_permissions(&slow, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+        let (rendered, warning) = render(slow.to_str().unwrap());
+        assert_eq!(rendered, "┌─[!12]");
+        assert!(warning.contains("took longer than 500ms"), "{}", warning);
+    }
+
+    #[cfg(all(feature="platform", unix))]
+    #[test]
+    fn deadline() {
+        let dir = tempfile::tempdir().unwrap();
+        let fifo = dir.path().join("fifo");
+        // Opening a FIFO blocks until something opens its other end, which nothing does
+        nix::unistd::mkfifo(&fifo, nix::sys::stat::Mode::S_IRWXU).unwrap();
+        let mut stdout = Vec::new();
+        let env = [
+            ("OMNIPROMPT_FIELDS", "histnum, file, exit_code"), ("OMNIPROMPT_HISTNUM", "12"), ("OMNIPROMPT_FILE_SHOW", fifo.to_str().unwrap()),
+            ("OMNIPROMPT_DEADLINE_MS", "200"), ("OMNIPROMPT_COLOR", "never"),
+        ];
+        let start = std::time::Instant::now();
+        print_default(Some(0), config(&env), &mut stdout, &mut Vec::new()).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(String::from_utf8(stdout).unwrap(), "┌─[!12] - […] - […]");
+        let mut stdout = Vec::new();
+        print_default(Some(0), config(&[&env[..], &[("OMNIPROMPT_DEADLINE_PLACEHOLDER", "⏱")]].concat()), &mut stdout, &mut Vec::new()).unwrap();
+        assert_eq!(String::from_utf8(stdout).unwrap(), "┌─[!12] - [⏱] - [⏱]");
+    }
+
+    #[test]
+    fn long_prompt() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("huge");
+        std::fs::write(&path, "x".repeat(FILE_SHOW_WIDTH)).unwrap();
+        let banner = "b".repeat(10_000);
+        let mut stdout = Vec::new();
+        let env = [("OMNIPROMPT_BANNER", banner.as_str()), ("OMNIPROMPT_FILE_SHOW", path.to_str().unwrap()), ("OMNIPROMPT_COLOR", "never")];
+        print_default(Some(0), config(&env), &mut stdout, &mut Vec::new()).unwrap();
+        let stdout = String::from_utf8(stdout).unwrap();
+        assert!(stdout.starts_with(&format!("{}\n", banner)));
+        assert!(stdout.contains(&"x".repeat(FILE_SHOW_WIDTH)));
+        assert!(stdout.ends_with("[$]> "));
+    }
+
+    #[test]
+    fn field_offsets() {
+        let mut stdout = Vec::new();
+        let start = (Local::now().timestamp() - 60).to_string();
+        let env = [("OMNIPROMPT_FIELD_OFFSETS", "1"), ("OMNIPROMPT_HISTNUM", "12"), ("OMNIPROMPT_BANNER", "banner"), ("OMNIPROMPT_SESSION_START", &start)];
+        print_default(Some(0), config(&env), &mut stdout, &mut Vec::new()).unwrap();
+        let stdout = String::from_utf8(stdout).unwrap();
+        let (rendered, trailer) = stdout.split_once(&format!("\x1b]{};", OFFSETS_OSC)).unwrap();
+        assert!(trailer.starts_with("[[\"whoami\","), "{}", trailer);
+        let histnum = trailer.split("[\"histnum\",").nth(1).unwrap();
+        let bounds: Vec<usize> = histnum.split(']').next().unwrap().split(',').map(|n| n.parse().unwrap()).collect();
+        assert_eq!(colors::strip(&rendered[bounds[0]..bounds[1]]), "!12");
+        assert_eq!(bounds[2], 3);
+        assert!(trailer.contains("[\"prompt\","));
+        // Named like the fields option and JSON records
+        assert!(trailer.contains("[\"session_time\","), "{}", trailer);
+
+        let has_trailer = |env: &[(&str, &str)]| {
+            let mut stdout = Vec::new();
+            print_default(Some(0), config(env), &mut stdout, &mut Vec::new()).unwrap();
+            String::from_utf8(stdout).unwrap().contains(&format!("\x1b]{};", OFFSETS_OSC))
+        };
+        assert!(!has_trailer(&[]));
+        assert!(!has_trailer(&[("OMNIPROMPT_FIELD_OFFSETS", "1"), ("OMNIPROMPT_SINGLE_LINE", "1")]));
+        #[cfg(unix)]
+        assert!(!has_trailer(&[("OMNIPROMPT_FIELD_OFFSETS", "1"), ("OMNIPROMPT_FILTER", "cat")]));
+    }
+
+    #[test]
+    fn compact_errors() {
+        let last_line = |env: &[(&str, &str)]| {
+            let mut stdout = Vec::new();
+            print_default(Some(0), config(env), &mut stdout, &mut Vec::new()).unwrap();
+            colors::strip(&String::from_utf8(stdout).unwrap()).lines().last().unwrap().to_string()
+        };
+        assert_eq!(last_line(&[("OMNIPROMPT_HISTNUM", "x")]), "└─[$]> ");
+        assert_eq!(last_line(&[("OMNIPROMPT_HISTNUM", "x"), ("OMNIPROMPT_COMPACT_ERRORS", "1")]), "$ ");
+        assert!(last_line(&[("OMNIPROMPT_COMPACT_ERRORS", "1")]).ends_with("[$]> "));
+    }
+
+    #[test]
+    fn strip() {
+        assert_eq!(colors::strip(&format!("{}-{}", "a".red().bold(), "b".dim())), "a-b");
+        assert_eq!(colors::strip("\x1b]133;A\x07a\x1b]0;title\x1b\\b"), "ab");
+    }
+
+    #[test]
+    fn width() {
+        assert_eq!(colors::width(&"main".green().to_string()), 4);
+        assert_eq!(colors::width("日本"), 4);
+        assert_eq!(colors::width("🔏 é"), 4);
+        assert_eq!(colors::width(""), 0);
     }
 }